@@ -1,8 +1,5 @@
-#![feature(slice_split_once)]
-
-use fax::{VecWriter, decoder, decoder::pels, BitWriter, Bits, Color};
-use std::io::Write;
-use std::fs::{self, File};
+use fax::{VecWriter, decoder, decoder::pels, netpbm, BitWriter, Bits, Color};
+use std::fs;
 
 fn main() {
     let mut args = std::env::args().skip(1);
@@ -10,16 +7,10 @@ fn main() {
     let reference = args.next().unwrap();
 
     let ref_data = std::fs::read(&reference).unwrap();
-    let (header1, data) = ref_data.split_once(|&b| b == b'\n').unwrap();
-    assert_eq!(header1, b"P4");
-    let (header2, ref_image) = data.split_once(|&b| b == b'\n').unwrap();
-    let header2 = std::str::from_utf8(header2).unwrap();
-    dbg!(header2);
-    let (w, h) = header2.split_once(" ").unwrap();
-    let width: u16 = w.parse().unwrap();
-    let h: u16 = h.parse().unwrap();
-
-    let mut ref_lines = ref_image.chunks_exact((width as usize + 7) / 8);
+    let images = netpbm::read(&ref_data).expect("not a PBM file");
+    let reference = images[0].to_bitmap().expect("not a bitmap");
+    let width = reference.width;
+    dbg!(width, reference.height);
 
     let data = fs::read(&input).unwrap();
     let mut height = 0;
@@ -33,9 +24,9 @@ fn main() {
             writer.write(bit);
         }
         writer.pad();
-        height += 1;
         let data = writer.finish();
-        let ref_line = ref_lines.next().unwrap();
+        let ref_line = reference.row(height);
+        height += 1;
         println!("{height:3} dec: {}", Line(&data));
         if ref_line != data {
             println!("    ref: {}", Line(ref_line));
@@ -1,4 +1,4 @@
-use fax::{VecWriter, encoder::Encoder, Color, ByteReader, tiff};
+use fax::{VecWriter, encoder::Encoder, netpbm, tiff};
 use std::fs;
 
 fn main() {
@@ -7,23 +7,16 @@ fn main() {
     let output = args.next().unwrap();
 
     let data = fs::read(&input).unwrap();
-    let mut parts = data.splitn(3, |&b| b == b'\n');
-    assert_eq!(parts.next().unwrap(), b"P4");
-    let mut size = parts.next().unwrap().splitn(2, |&b| b == b' ');
-    let width: u32 = std::str::from_utf8(size.next().unwrap()).unwrap().parse().unwrap();
-    let height: u32 = std::str::from_utf8(size.next().unwrap()).unwrap().parse().unwrap();
+    let images = netpbm::read(&data).expect("not a PBM file");
+    let image = &images[0];
+    let (width, height) = (image.header.width, image.header.height);
 
     let writer = VecWriter::new();
     let mut encoder = Encoder::new(writer);
     
-    for line in parts.next().unwrap().chunks((width as usize + 7) / 8) {
-        let line = ByteReader::new(line.iter().cloned()).into_bits().take(width as usize)
-        .map(|b| match b {
-            false => Color::White,
-            true => Color::Black
-        });
-        encoder.encode_line(line, width as u16);
+    for line in image.lines() {
+        encoder.encode_transitions(&line, width);
     }
     let data = encoder.finish().finish();
-    fs::write(&output, &tiff::wrap(&data, width, height)).unwrap();
+    fs::write(&output, tiff::wrap(&data, width as u32, height as u32)).unwrap();
 }
@@ -0,0 +1,140 @@
+use crate::runs;
+
+/// A bi-level image with the pels packed into bytes.
+///
+/// Each row starts on a byte boundary and holds 8 pels per byte, the most significant bit first.
+/// A set bit is black.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Bitmap {
+    pub width: u16,
+    pub height: u16,
+    pub data: Vec<u8>,
+}
+impl Bitmap {
+    /// A white image of the given size.
+    pub fn new(width: u16, height: u16) -> Self {
+        let stride = (width as usize).div_ceil(8);
+        Bitmap { width, height, data: vec![0; stride * height as usize] }
+    }
+    /// An image without rows, for use with `push_line`.
+    pub fn empty(width: u16) -> Self {
+        Bitmap::new(width, 0)
+    }
+    /// Build an image from lines given as list of color changes.
+    pub fn from_lines<L: AsRef<[u16]>>(lines: impl IntoIterator<Item=L>, width: u16) -> Self {
+        let mut bitmap = Bitmap::empty(width);
+        for line in lines {
+            bitmap.push_line(line.as_ref());
+        }
+        bitmap
+    }
+    /// Number of bytes per row.
+    pub fn stride(&self) -> usize {
+        (self.width as usize).div_ceil(8)
+    }
+    pub fn row(&self, y: u16) -> &[u8] {
+        let stride = self.stride();
+        &self.data[y as usize * stride .. (y as usize + 1) * stride]
+    }
+    pub fn row_mut(&mut self, y: u16) -> &mut [u8] {
+        let stride = self.stride();
+        &mut self.data[y as usize * stride .. (y as usize + 1) * stride]
+    }
+    pub fn get(&self, x: u16, y: u16) -> bool {
+        self.row(y)[x as usize / 8] & (0x80 >> (x % 8)) != 0
+    }
+    pub fn set(&mut self, x: u16, y: u16, black: bool) {
+        let byte = &mut self.row_mut(y)[x as usize / 8];
+        if black {
+            *byte |= 0x80 >> (x % 8);
+        } else {
+            *byte &= !(0x80 >> (x % 8));
+        }
+    }
+    /// Append a row given as list of color changes (as produced by the decoder).
+    pub fn push_line(&mut self, line: &[u16]) {
+        let stride = self.stride();
+        let start = self.data.len();
+        self.data.resize(start + stride, 0);
+        fill_spans(&mut self.data[start ..], line, self.width);
+        self.height += 1;
+    }
+    /// The row `y` as list of color changes (as accepted by the encoder).
+    pub fn line(&self, y: u16) -> Vec<u16> {
+        packed_line(self.row(y), self.width)
+    }
+    /// Swap black and white.
+    pub fn invert(&mut self) {
+        for b in &mut self.data {
+            *b = !*b;
+        }
+        self.clear_padding();
+    }
+    /// Clear the unused bits at the end of each row.
+    pub fn clear_padding(&mut self) {
+        let stride = self.stride();
+        if self.width.is_multiple_of(8) || stride == 0 {
+            return;
+        }
+        let mask = 0xFF << (8 - self.width % 8);
+        for row in self.data.chunks_mut(stride) {
+            row[stride - 1] &= mask;
+        }
+    }
+    /// Iterate over all rows as lists of color changes.
+    pub fn lines(&self) -> impl Iterator<Item=Vec<u16>> + '_ {
+        (0 .. self.height).map(move |y| self.line(y))
+    }
+}
+
+/// Convert a packed row to a list of color changes.
+pub(crate) fn packed_line(row: &[u8], width: u16) -> Vec<u16> {
+    let mut line = vec![];
+    let mut black = false;
+    for (i, &byte) in row.iter().enumerate() {
+        // skip bytes without a change
+        if byte == if black { 0xFF } else { 0 } {
+            continue;
+        }
+        for bit in 0 .. 8 {
+            let x = (i * 8 + bit) as u16;
+            if x >= width {
+                break;
+            }
+            if (byte & (0x80 >> bit) != 0) != black {
+                black = !black;
+                line.push(x);
+            }
+        }
+    }
+    line
+}
+
+/// Set the bits of the black runs of `line` in `row`.
+pub(crate) fn fill_spans(row: &mut [u8], line: &[u16], width: u16) {
+    for (start, end) in runs::spans(line, width) {
+        let (start, end) = (start as usize, end as usize);
+        let (first, last) = (start / 8, (end - 1) / 8);
+        let head = 0xFFu8 >> (start % 8);
+        let tail = 0xFFu8 << (7 - (end - 1) % 8);
+        if first == last {
+            row[first] |= head & tail;
+        } else {
+            row[first] |= head;
+            for b in &mut row[first + 1 .. last] {
+                *b = 0xFF;
+            }
+            row[last] |= tail;
+        }
+    }
+}
+
+#[test]
+fn test_bitmap_lines() {
+    let lines: Vec<Vec<u16>> = vec![vec![], vec![0, 3, 7, 8, 9, 17], vec![5], vec![16, 18]];
+    let bitmap = Bitmap::from_lines(&lines, 19);
+    assert_eq!(bitmap.row(1), &[0b1110_0001, 0b0111_1111, 0b1000_0000]);
+    assert_eq!(bitmap.row(2), &[0b0000_0111, 0xFF, 0b1110_0000]);
+    assert!(bitmap.get(17, 3) && !bitmap.get(18, 3));
+    assert_eq!(bitmap.lines().collect::<Vec<_>>(), lines);
+}
@@ -0,0 +1,139 @@
+use std::convert::TryFrom;
+use crate::{Coding, Resolution};
+use crate::bitmap::Bitmap;
+use crate::encoder::Encoder;
+use crate::tiff::{self, Image, Options, Photometric};
+use crate::VecWriter;
+
+/// Rows are padded to 4 bytes.
+fn padded_stride(width: u16) -> usize {
+    (width as usize).div_ceil(32) * 4
+}
+
+fn dpi_to_ppm(dpi: u16) -> u32 {
+    (dpi as u32 * 10000 + 127) / 254
+}
+
+fn ppm_to_dpi(ppm: u32) -> u16 {
+    ((ppm as u64 * 254 + 5000) / 10000).min(u16::MAX as u64) as u16
+}
+
+/// Write a bitmap as 1 bpp BMP, bottom-up, with a palette of white and black.
+pub fn write(bitmap: &Bitmap, resolution: Option<Resolution>) -> Vec<u8> {
+    let stride = padded_stride(bitmap.width);
+    let image_size = (stride * bitmap.height as usize) as u32;
+    let offset = 14 + 40 + 8;
+    let (x, y) = resolution.map(|r| (dpi_to_ppm(r.x), dpi_to_ppm(r.y))).unwrap_or((0, 0));
+
+    let mut out = Vec::with_capacity(offset as usize + image_size as usize);
+    out.extend_from_slice(b"BM");
+    for n in [offset + image_size, 0, offset] {
+        out.extend_from_slice(&n.to_le_bytes());
+    }
+    out.extend_from_slice(&40u32.to_le_bytes());
+    out.extend_from_slice(&(bitmap.width as i32).to_le_bytes());
+    out.extend_from_slice(&(bitmap.height as i32).to_le_bytes());
+    out.extend_from_slice(&1u16.to_le_bytes()); // planes
+    out.extend_from_slice(&1u16.to_le_bytes()); // bits per pixel
+    for n in [0, image_size, x, y, 2, 2] {
+        out.extend_from_slice(&n.to_le_bytes());
+    }
+    // palette as BGRx: index 0 white, index 1 black, just like the bitmap bits
+    out.extend_from_slice(&[0xFF, 0xFF, 0xFF, 0, 0, 0, 0, 0]);
+
+    for y in (0 .. bitmap.height).rev() {
+        let row = bitmap.row(y);
+        out.extend_from_slice(row);
+        out.resize(out.len() + stride - row.len(), 0);
+    }
+    out
+}
+
+/// Read a 1 bpp BMP.
+///
+/// Pels are black if their palette entry is the darker one. Returns the image and
+/// the resolution, if given.
+pub fn read(data: &[u8]) -> Option<(Bitmap, Option<Resolution>)> {
+    let u16_at = |pos: usize| data.get(pos .. pos + 2).map(|b| u16::from_le_bytes([b[0], b[1]]));
+    let u32_at = |pos: usize| data.get(pos .. pos + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+    if data.get(.. 2)? != b"BM" {
+        return None;
+    }
+    let offset = u32_at(10)? as usize;
+    let header_size = u32_at(14)? as usize;
+    if header_size < 40 {
+        return None;
+    }
+    let width = u16::try_from(u32_at(18)? as i32).ok()?;
+    let height = u32_at(22)? as i32;
+    // negative heights are stored top-down
+    let (height, top_down) = (u16::try_from(height.unsigned_abs()).ok()?, height < 0);
+    if u16_at(28)? != 1 || u32_at(30)? != 0 {
+        return None;
+    }
+    let (x, y) = (u32_at(38)?, u32_at(42)?);
+    let resolution = if x > 0 && y > 0 { Some(Resolution { x: ppm_to_dpi(x), y: ppm_to_dpi(y) }) } else { None };
+
+    let palette = data.get(14 + header_size .. 14 + header_size + 8)?;
+    let luma = |c: &[u8]| c[0] as u32 * 114 + c[1] as u32 * 587 + c[2] as u32 * 299;
+    let invert = luma(&palette[0 .. 4]) < luma(&palette[4 .. 8]);
+
+    let stride = padded_stride(width);
+    let mut bitmap = Bitmap::new(width, height);
+    let row_len = bitmap.stride();
+    for y in 0 .. height {
+        let src = if top_down { y } else { height - 1 - y } as usize;
+        let row = data.get(offset + src * stride .. offset + src * stride + row_len)?;
+        bitmap.row_mut(y).copy_from_slice(row);
+    }
+    if invert {
+        bitmap.invert();
+    } else {
+        bitmap.clear_padding();
+    }
+    Some((bitmap, resolution))
+}
+
+/// Convert a 1 bpp BMP into a single page Group 4 TIFF.
+pub fn bmp_to_tiff(data: &[u8], options: Options) -> Option<Vec<u8>> {
+    let (bitmap, resolution) = read(data)?;
+    let mut encoder = Encoder::new(VecWriter::new());
+    for line in bitmap.lines() {
+        encoder.encode_transitions(&line, bitmap.width);
+    }
+    let image = Image {
+        width: bitmap.width as u32,
+        height: bitmap.height as u32,
+        coding: Coding::Group4,
+        photometric: Photometric::WhiteIsZero,
+        resolution,
+        rows_per_strip: bitmap.height as u32,
+        strips: vec![encoder.finish().finish()],
+    };
+    Some(tiff::write(&[image], options))
+}
+
+/// Convert the first page of a TIFF file into a 1 bpp BMP.
+pub fn tiff_to_bmp(data: &[u8]) -> Option<Vec<u8>> {
+    let image = tiff::read(data)?.into_iter().next()?;
+    let mut bitmap = Bitmap::empty(u16::try_from(image.width).ok()?);
+    image.decode(|line| bitmap.push_line(line))?;
+    if image.photometric == Photometric::BlackIsZero {
+        bitmap.invert();
+    }
+    Some(write(&bitmap, image.resolution))
+}
+
+#[test]
+fn test_bmp() {
+    let bitmap = Bitmap::from_lines(&[vec![0, 3], vec![], vec![30, 33], vec![5, 6]], 35);
+    let data = write(&bitmap, Some(Resolution::FINE));
+    assert_eq!(data.len(), 62 + 8 * 4);
+    // the last row comes first
+    assert_eq!(&data[62 .. 70], &[0b0000_0100, 0, 0, 0, 0, 0, 0, 0]);
+    assert_eq!(read(&data), Some((bitmap.clone(), Some(Resolution::FINE))));
+
+    let tiff = bmp_to_tiff(&data, Options::default()).unwrap();
+    assert_eq!(tiff::read(&tiff).unwrap()[0].coding, Coding::Group4);
+    assert_eq!(tiff_to_bmp(&tiff).unwrap(), data);
+}
@@ -0,0 +1,186 @@
+use crate::decoder::decode_g4;
+use crate::transform::{transform_lines, Transform};
+
+/// Size of a header record.
+const RECORD_LEN: usize = 128;
+/// The header is 16 records, the image data starts after it.
+const HEADER_LEN: usize = 16 * RECORD_LEN;
+
+/// Directions of the pel path and of the line progression, in degrees counterclockwise
+/// from the positive x axis. Upright images are `000,270`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Orientation {
+    pub pel_path: u16,
+    pub line_progression: u16,
+}
+impl Orientation {
+    pub const UPRIGHT: Orientation = Orientation { pel_path: 0, line_progression: 270 };
+
+    /// The transforms that turn the stored image upright.
+    pub fn transforms(self) -> Option<&'static [Transform]> {
+        use Transform::*;
+        Some(match (self.pel_path, self.line_progression) {
+            (0, 270) => &[],
+            (90, 0) => &[Rotate270],
+            (180, 90) => &[Rotate180],
+            (270, 180) => &[Rotate90],
+            (0, 90) => &[FlipVertical],
+            (180, 270) => &[FlipHorizontal],
+            // pels and lines are swapped
+            (270, 0) => &[Rotate90, FlipHorizontal],
+            (90, 180) => &[Rotate270, FlipHorizontal],
+            _ => return None,
+        })
+    }
+}
+
+/// The header of a CALS Type I raster file.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Header {
+    pub orientation: Orientation,
+    /// Pels per line, as stored.
+    pub width: u16,
+    /// Number of lines, as stored.
+    pub height: u16,
+    /// Resolution in dots per inch.
+    pub density: u16,
+}
+impl Header {
+    /// Parse the header records. Only Type I (Group 4 coded) files are accepted.
+    pub fn parse(data: &[u8]) -> Option<Header> {
+        let numbers = |value: &str| -> Option<Vec<u16>> {
+            value.split(',').map(|n| n.trim().parse().ok()).collect()
+        };
+        let mut rtype = None;
+        let mut orientation = Orientation::UPRIGHT;
+        let mut size = None;
+        let mut density = None;
+        for record in data.get(.. HEADER_LEN)?.chunks(RECORD_LEN) {
+            // free text records such as `notes` may use any character set
+            let (key, value) = match std::str::from_utf8(record).ok().and_then(|r| r.split_once(':')) {
+                Some(pair) => pair,
+                None => continue,
+            };
+            let value = value.trim_matches(|c: char| c.is_whitespace() || c == '\0');
+            match &*key.trim().to_ascii_lowercase() {
+                "rtype" => rtype = Some(value.to_string()),
+                "rorient" => match numbers(value)?[..] {
+                    [pel_path, line_progression] => orientation = Orientation { pel_path, line_progression },
+                    _ => return None,
+                },
+                "rpelcnt" => match numbers(value)?[..] {
+                    [width, height] => size = Some((width, height)),
+                    _ => return None,
+                },
+                "rdensty" => density = value.parse().ok(),
+                _ => {}
+            }
+        }
+        if rtype.as_deref() != Some("1") {
+            return None;
+        }
+        let (width, height) = size?;
+        Some(Header { orientation, width, height, density: density? })
+    }
+    /// The 16 header records, with `NONE` for the fields that are not known.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let records = [
+            "srcdocid: NONE".to_string(),
+            "dstdocid: NONE".to_string(),
+            "txtfilid: NONE".to_string(),
+            "figid: NONE".to_string(),
+            "srcgph: NONE".to_string(),
+            "doccls: NONE".to_string(),
+            "rtype: 1".to_string(),
+            format!("rorient: {:03},{:03}", self.orientation.pel_path, self.orientation.line_progression),
+            format!("rpelcnt: {:06},{:06}", self.width, self.height),
+            format!("rdensty: {:04}", self.density),
+            "notes: NONE".to_string(),
+        ];
+        let mut out = Vec::with_capacity(HEADER_LEN);
+        for record in &records {
+            out.extend_from_slice(record.as_bytes());
+            out.resize(out.len().next_multiple_of(RECORD_LEN), b' ');
+        }
+        out.resize(HEADER_LEN, b' ');
+        out
+    }
+}
+
+/// A decoded CALS image, turned upright.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Page {
+    pub width: u16,
+    /// Resolution in dots per inch.
+    pub density: u16,
+    /// Lines as list of color changes, starting with white.
+    pub lines: Vec<Vec<u16>>,
+}
+
+/// Read a CALS Type I file and decode its image with `decode_g4`.
+pub fn read(data: &[u8]) -> Option<Page> {
+    let header = Header::parse(data)?;
+    let transforms = header.orientation.transforms()?;
+    let mut lines = vec![];
+    decode_g4(data[HEADER_LEN ..].iter().cloned(), header.width, Some(header.height), |line| lines.push(line.to_vec()))?;
+    let mut width = header.width;
+    for &transform in transforms {
+        (lines, width) = transform_lines(&lines, width, transform);
+    }
+    Some(Page { width, density: header.density, lines })
+}
+
+/// Write a CALS Type I file from Group 4 data, as produced by `encoder::Encoder`.
+pub fn write(header: &Header, data: &[u8]) -> Vec<u8> {
+    let mut out = header.to_bytes();
+    out.extend_from_slice(data);
+    out
+}
+
+#[test]
+fn test_cals() {
+    use crate::VecWriter;
+    use crate::encoder::Encoder;
+
+    let encode = |lines: &[Vec<u16>], width| {
+        let mut encoder = Encoder::new(VecWriter::new());
+        for line in lines {
+            encoder.encode_transitions(line, width);
+        }
+        encoder.finish().finish()
+    };
+    let lines = crate::test_lines(90, 150);
+    let header = Header { orientation: Orientation::UPRIGHT, width: 150, height: 90, density: 200 };
+    let data = write(&header, &encode(&lines, 150));
+    assert_eq!(&data[5 * RECORD_LEN .. 6 * RECORD_LEN - 116], b"doccls: NONE");
+    assert_eq!(&data[7 * RECORD_LEN .. 8 * RECORD_LEN - 112], b"rorient: 000,270");
+    assert_eq!(&data[8 * RECORD_LEN .. 9 * RECORD_LEN - 106], b"rpelcnt: 000150,000090");
+    assert_eq!(Header::parse(&data), Some(header));
+    assert_eq!(read(&data), Some(Page { width: 150, density: 200, lines: lines.clone() }));
+
+    // stored rotated by 90 and 270 degrees, upside down, and mirrored along the diagonal
+    let stored = [
+        (Orientation { pel_path: 90, line_progression: 0 }, Transform::Rotate90),
+        (Orientation { pel_path: 270, line_progression: 180 }, Transform::Rotate270),
+        (Orientation { pel_path: 180, line_progression: 90 }, Transform::Rotate180),
+    ];
+    for &(orientation, transform) in &stored {
+        let (rotated, width) = transform_lines(&lines, 150, transform);
+        let header = Header { orientation, width, height: rotated.len() as u16, density: 300 };
+        let page = read(&write(&header, &encode(&rotated, width))).unwrap();
+        assert_eq!((page.width, page.density), (150, 300));
+        assert_eq!(page.lines, lines);
+    }
+    let (transposed, width) = transform_lines(&transform_lines(&lines, 150, Transform::FlipHorizontal).0, 150, Transform::Rotate270);
+    let header = Header { orientation: Orientation { pel_path: 270, line_progression: 0 }, width, height: 150, density: 300 };
+    assert_eq!(read(&write(&header, &encode(&transposed, width))).unwrap().lines, lines);
+
+    // other writers leave out the leading zeros and pad with NULs
+    let mut data = data;
+    data[9 * RECORD_LEN .. 10 * RECORD_LEN].fill(0);
+    data[9 * RECORD_LEN .. 9 * RECORD_LEN + 12].copy_from_slice(b"rdensty: 300");
+    assert_eq!(Header::parse(&data).unwrap().density, 300);
+    data[10 * RECORD_LEN .. 10 * RECORD_LEN + 12].copy_from_slice(b"notes: Gr\xFC\xDFe");
+    assert_eq!(Header::parse(&data).unwrap().density, 300);
+    assert_eq!(Header::parse(&data[.. HEADER_LEN - 1]), None);
+}
@@ -2,11 +2,17 @@ use crate::{BitReader, ByteReader, Color, Transitions};
 use crate::maps::{Mode, black, white, mode, EDFB_HALF, EOL};
 
 
+/// No code starts with eight zeros, so this can only be (part of) an EOL.
+fn at_eol(reader: &impl BitReader) -> bool {
+    reader.peek(8) == Some(0)
+}
+
 fn with_markup<D, R>(decoder: D, reader: &mut R) -> Option<u16>
-    where D: Fn(&mut R) -> Option<u16>
+    where D: Fn(&mut R) -> Option<u16>, R: BitReader
 {
     let mut sum = 0;
-    while let Some(n) = decoder(reader) {
+    while !at_eol(reader) {
+        let n = decoder(reader)?;
         //print!("{} ", n);
         sum += n;
         if n < 64 {
@@ -82,6 +88,211 @@ pub fn decode_g3(input: impl Iterator<Item=u8>, mut line_cb: impl FnMut(&[u16]))
     Some(())
 }
 
+/// Statistics about damaged lines found while decoding Group 3 data.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct LineStats {
+    /// Number of lines, including the damaged ones.
+    pub lines: u32,
+    /// Number of lines that could not be decoded.
+    pub bad_lines: u32,
+    /// Longest run of consecutive damaged lines.
+    pub consecutive_bad_lines: u32,
+}
+
+/// Consume fill bits and an EOL code. Returns false if there is no EOL at the current position.
+fn read_eol(reader: &mut impl BitReader) -> bool {
+    loop {
+        match reader.peek(EOL.len) {
+            Some(bits) if bits == EOL.data => {
+                reader.consume(EOL.len);
+                return true;
+            }
+            Some(0) => reader.consume(1),
+            _ => return false,
+        }
+    }
+}
+
+/// Skip data until the next EOL code (which is consumed).
+fn skip_to_eol(reader: &mut impl BitReader) -> bool {
+    while let Some(bits) = reader.peek(EOL.len) {
+        if bits == EOL.data {
+            reader.consume(EOL.len);
+            return true;
+        }
+        reader.consume(1);
+    }
+    false
+}
+
+/// Decode one line of Modified Huffman runs into `current`.
+///
+/// Fails unless the runs add up to exactly `width`.
+pub(crate) fn decode_1d_line(reader: &mut impl BitReader, current: &mut Vec<u16>, width: u16) -> Option<()> {
+    let mut a0 = 0;
+    let mut color = Color::White;
+    while a0 < width {
+        a0 += colored(color, reader)?;
+        if a0 < width {
+            current.push(a0);
+        }
+        color = !color;
+    }
+    if a0 == width {
+        Some(())
+    } else {
+        None
+    }
+}
+
+/// Decode Group 3 data as received from a fax line, where lines may be damaged.
+///
+/// - `width` is the width of the image.
+/// - `two_d` has to be set if the data was coded with the two-dimensional (MR) scheme,
+///   that is every EOL is followed by a tag bit.
+/// - The callback `line_cb` is called for each line, as in `decode_g3`.
+///
+/// A line that cannot be decoded, or that does not have exactly `width` pels, is replaced
+/// by the previous line (or a white line at the top of the page), as fax receivers do.
+/// Decoding continues at the next EOL. The returned `LineStats` count these lines.
+pub fn decode_g3_checked(input: impl Iterator<Item=u8>, width: u16, two_d: bool, mut line_cb: impl FnMut(&[u16])) -> LineStats {
+    let mut reader = ByteReader::new(input);
+    let mut reference: Vec<u16> = vec![];
+    let mut current: Vec<u16> = vec![];
+    let mut stats = LineStats::default();
+    let mut consecutive = 0;
+
+    // the leading EOL is optional
+    read_eol(&mut reader);
+    loop {
+        let one_d = if two_d {
+            match reader.peek(1) {
+                Some(tag) => {
+                    reader.consume(1);
+                    tag == 1
+                }
+                None => break,
+            }
+        } else {
+            true
+        };
+        // a second EOL directly after the first one starts the RTC (or ends the data)
+        if reader.peek(8).is_none_or(|bits| bits == 0) {
+            break;
+        }
+
+        let ok = if one_d {
+            decode_1d_line(&mut reader, &mut current, width).is_some()
+        } else {
+            decode_2d_line(&mut reader, &reference, &mut current, width) == Some(true)
+        } && read_eol(&mut reader);
+
+        stats.lines += 1;
+        if ok {
+            consecutive = 0;
+            line_cb(&current);
+            std::mem::swap(&mut reference, &mut current);
+        } else {
+            stats.bad_lines += 1;
+            consecutive += 1;
+            stats.consecutive_bad_lines = stats.consecutive_bad_lines.max(consecutive);
+            line_cb(&reference);
+            if !skip_to_eol(&mut reader) {
+                break;
+            }
+        }
+        current.clear();
+    }
+    stats
+}
+
+/// Decode one two-dimensionally coded line into `current`.
+///
+/// Returns `Some(true)` if a line was decoded and `Some(false)` if no further line could be found
+/// (end of data or an extension code). Returns `None` if a change lies beyond the end of the line.
+pub(crate) fn decode_2d_line(reader: &mut impl BitReader, reference: &[u16], current: &mut Vec<u16>, width: u16) -> Option<bool> {
+    let mut transitions = Transitions::new(reference);
+    let mut a0 = 0;
+    let mut color = Color::White;
+    let mut start_of_row = true;
+    //println!("\n\nline {}", y);
+
+    loop {
+        if at_eol(reader) {
+            return Some(false);
+        }
+        let mode = match mode::decode(reader) {
+            Some(mode) => mode,
+            None => return Some(false),
+        };
+        //println!("  {:?}, color={:?}, a0={}", mode, color, a0);
+
+        match mode {
+            Mode::Pass => {
+                let _ = transitions.next_color(a0, !color, start_of_row)?;
+                //println!("b1={}", b1);
+                if let Some(b2) = transitions.next() {
+                    //println!("b2={}", b2);
+                    a0 = b2;
+                }
+                if a0 > width {
+                    return None;
+                }
+            }
+            Mode::Vertical(delta) => {
+                let b1 = transitions.next_color(a0, !color, start_of_row).unwrap_or(width);
+                let a1 = (b1 as i16 + delta as i16) as u16;
+                if a1 > width {
+                    return None;
+                }
+                if a1 == width {
+                    break;
+                }
+                //println!("transition to {:?} at {}", !color, a1);
+                current.push(a1);
+                color = !color;
+                a0 = a1;
+                if delta < 0 {
+                    transitions.seek_back(a0);
+                }
+            }
+            Mode::Horizontal => {
+                let a0a1 = colored(color, reader)?;
+                let a1a2 = colored(!color, reader)?;
+                let a1 = a0 + a0a1;
+                let a2 = a1 + a1a2;
+                //println!("a0a1={}, a1a2={}, a1={}, a2={}", a0a1, a1a2, a1, a2);
+                if a2 > width {
+                    return None;
+                }
+
+                if a1 < width {
+                    current.push(a1);
+                }
+                if a2 >= width {
+                    break;
+                }
+                current.push(a2);
+                a0 = a2;
+            }
+            Mode::Extension => {
+                let _xxx = reader.peek(3)?;
+                //println!("extension: {:03b}", xxx);
+                reader.consume(3);
+                //println!("{:?}", current);
+                return Some(false);
+            }
+        }
+        start_of_row = false;
+
+        if a0 >= width {
+            break;
+        }
+    }
+    //println!("{:?}", current);
+    Some(true)
+}
+
 /// Decode a Group 4 Image
 /// 
 /// - `width` is the width of the image.
@@ -90,6 +301,8 @@ pub fn decode_g3(input: impl Iterator<Item=u8>, mut line_cb: impl FnMut(&[u16]))
 /// 
 ///   If `height` is specified, at most that many lines will be decoded,
 ///   otherwise data is decoded until the end-of-block marker (or end of data).
+///   Coders may leave out the white lines at the bottom of the page, so if the
+///   end-of-block marker comes before `height` lines, the missing lines are passed as white.
 /// 
 /// To obtain an iterator over the pixel colors, the `pels` function is provided.
 pub fn decode_g4(input: impl Iterator<Item=u8>, width: u16, height: Option<u16>, mut line_cb: impl FnMut(&[u16])) -> Option<()> {
@@ -98,82 +311,72 @@ pub fn decode_g4(input: impl Iterator<Item=u8>, width: u16, height: Option<u16>,
     let mut current: Vec<u16> = vec![];
 
     let limit = height.unwrap_or(u16::MAX);
-    'outer: for _ in 0 .. limit {
-        let mut transitions = Transitions::new(&reference);
-        let mut a0 = 0;
-        let mut color = Color::White;
-        let mut start_of_row = true;
-        //println!("\n\nline {}", y);
-        
-        loop {
-            let mode = match mode::decode(&mut reader) {
-                Some(mode) => mode,
-                None => break 'outer,
-            };
-            //println!("  {:?}, color={:?}, a0={}", mode, color, a0);
-            
-            match mode {
-                Mode::Pass => {
-                    let _ = transitions.next_color(a0, !color, false)?;
-                    //println!("b1={}", b1);
-                    if let Some(b2) = transitions.next() {
-                        //println!("b2={}", b2);
-                        a0 = b2;
-                    }
-                }
-                Mode::Vertical(delta) => {
-                    let b1 = transitions.next_color(a0, !color, start_of_row).unwrap_or(width);
-                    let a1 = (b1 as i16 + delta as i16) as u16;
-                    if a1 >= width {
-                        break;
-                    }
-                    //println!("transition to {:?} at {}", !color, a1);
-                    current.push(a1);
-                    color = !color;
-                    a0 = a1;
-                    if delta < 0 {
-                        transitions.seek_back(a0);
-                    }
-                }
-                Mode::Horizontal => {
-                    let a0a1 = colored(color, &mut reader)?;
-                    let a1a2 = colored(!color, &mut reader)?;
-                    let a1 = a0 + a0a1;
-                    let a2 = a1 + a1a2;
-                    //println!("a0a1={}, a1a2={}, a1={}, a2={}", a0a1, a1a2, a1, a2);
-                    
-                    current.push(a1);
-                    if a2 >= width {
-                        break;
-                    }
-                    current.push(a2);
-                    a0 = a2;
-                }
-                Mode::Extension => {
-                    let _xxx = reader.peek(3)?;
-                    //println!("extension: {:03b}", xxx);
-                    reader.consume(3);
-                    //println!("{:?}", current);
-                    break 'outer;
-                }
-            }
-            start_of_row = false;
-
-            if a0 >= width {
-                break;
-            }
+    let mut lines = 0;
+    while lines < limit {
+        if !decode_2d_line(&mut reader, &reference, &mut current, width)? {
+            break;
         }
-        //println!("{:?}", current);
-
         line_cb(&current);
         std::mem::swap(&mut reference, &mut current);
         current.clear();
+        lines += 1;
     }
-    if height.is_none() {
-        reader.expect(EDFB_HALF).ok()?;
-        reader.expect(EDFB_HALF).ok()?;
+    match height {
+        None => {
+            reader.expect(EDFB_HALF).ok()?;
+            reader.expect(EDFB_HALF).ok()?;
+        }
+        Some(height) if reader.expect(EDFB_HALF).is_ok() => {
+            for _ in lines .. height {
+                line_cb(&[]);
+            }
+        }
+        Some(_) => {}
     }
     //reader.print_remaining();
 
     Some(())
 }
+
+#[test]
+fn test_decode_g4_early_end() {
+    use crate::{VecWriter, encoder::Encoder};
+
+    let mut encoder = Encoder::new(VecWriter::new());
+    encoder.encode_transitions(&[10, 20], 100);
+    encoder.encode_transitions(&[], 100);
+    let data = encoder.finish().finish();
+
+    let mut lines = vec![];
+    decode_g4(data.iter().cloned(), 100, None, |line| lines.push(line.to_vec())).unwrap();
+    assert_eq!(lines, [vec![10, 20], vec![]]);
+
+    lines.clear();
+    decode_g4(data.iter().cloned(), 100, Some(4), |line| lines.push(line.to_vec())).unwrap();
+    assert_eq!(lines, [vec![10, 20], vec![], vec![], vec![]]);
+}
+
+#[test]
+fn test_decode_g3_overshoot() {
+    use crate::{BitWriter, Bits, VecWriter, encoder::encode_1d_line};
+
+    // the MR line puts a change 3 pels past the end of the line
+    let mut writer = VecWriter::new();
+    let tag = |writer: &mut VecWriter, one_d| {
+        writer.write(EOL);
+        writer.write(Bits { data: one_d as u16, len: 1 });
+    };
+    tag(&mut writer, true);
+    encode_1d_line(&mut writer, &[98], 100);
+    tag(&mut writer, false);
+    writer.write(mode::encode(Mode::Vertical(3)).unwrap());
+    tag(&mut writer, true);
+    encode_1d_line(&mut writer, &[10], 100);
+    for _ in 0 .. 6 {
+        tag(&mut writer, true);
+    }
+    let mut lines = vec![];
+    let stats = decode_g3_checked(writer.finish().iter().cloned(), 100, true, |line| lines.push(line.to_vec()));
+    assert_eq!((stats.lines, stats.bad_lines), (3, 1));
+    assert_eq!(lines, [vec![98], vec![98], vec![10]]);
+}
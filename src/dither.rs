@@ -0,0 +1,154 @@
+use crate::{Coding, Color};
+use crate::preview::GrayImage;
+use crate::transcode::AnyEncoder;
+
+/// How grayscale values are mapped to black and white.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Method {
+    /// Values below the threshold are black. Use `otsu` to pick one from a histogram.
+    Threshold(u8),
+    /// Error diffusion with the Floyd-Steinberg weights.
+    FloydSteinberg,
+    /// Ordered dithering with an 8x8 Bayer matrix.
+    Ordered,
+}
+
+const BAYER: [[u8; 8]; 8] = [
+    [ 0, 32,  8, 40,  2, 34, 10, 42],
+    [48, 16, 56, 24, 50, 18, 58, 26],
+    [12, 44,  4, 36, 14, 46,  6, 38],
+    [60, 28, 52, 20, 62, 30, 54, 22],
+    [ 3, 35, 11, 43,  1, 33,  9, 41],
+    [51, 19, 59, 27, 49, 17, 57, 25],
+    [15, 47,  7, 39, 13, 45,  5, 37],
+    [63, 31, 55, 23, 61, 29, 53, 21],
+];
+
+/// Count the pixels of each value.
+pub fn histogram<'a>(rows: impl IntoIterator<Item=&'a [u8]>) -> [u32; 256] {
+    let mut histogram = [0; 256];
+    for row in rows {
+        for &v in row {
+            histogram[v as usize] += 1;
+        }
+    }
+    histogram
+}
+
+/// The threshold that best separates the histogram into two classes (Otsu's method).
+///
+/// The result is meant for `Method::Threshold`: values below it belong to the dark class.
+/// The histogram has to cover the whole image, so it has to be buffered (or read twice);
+/// when converting rows as they arrive, pass a fixed threshold to `Binarizer` instead.
+pub fn otsu(histogram: &[u32; 256]) -> u8 {
+    let total: u64 = histogram.iter().map(|&n| n as u64).sum();
+    let sum: u64 = histogram.iter().enumerate().map(|(v, &n)| v as u64 * n as u64).sum();
+    let (mut count_dark, mut sum_dark) = (0u64, 0u64);
+    let (mut best, mut best_variance) = (128, 0.0);
+    for t in 1 .. 256 {
+        count_dark += histogram[t - 1] as u64;
+        sum_dark += (t as u64 - 1) * histogram[t - 1] as u64;
+        let count_light = total - count_dark;
+        if count_dark == 0 || count_light == 0 {
+            continue;
+        }
+        let mean_dark = sum_dark as f64 / count_dark as f64;
+        let mean_light = (sum - sum_dark) as f64 / count_light as f64;
+        let variance = count_dark as f64 * count_light as f64 * (mean_dark - mean_light).powi(2);
+        if variance > best_variance {
+            best = t as u8;
+            best_variance = variance;
+        }
+    }
+    best
+}
+
+/// Converts 8-bit grayscale rows (0 is black) to bi-level rows, one row at a time.
+///
+/// Only the diffused errors of one row are kept, so rows can be converted as they arrive
+/// and passed on to an encoder.
+pub struct Binarizer {
+    width: u16,
+    method: Method,
+    row: usize,
+    /// diffused error for the current and the next row, with one pixel of margin on each side
+    errors: [Vec<i32>; 2],
+}
+impl Binarizer {
+    pub fn new(width: u16, method: Method) -> Self {
+        let errors = vec![0; width as usize + 2];
+        Binarizer { width, method, row: 0, errors: [errors.clone(), errors] }
+    }
+    /// Convert the next row. `row` has to hold at least `width` values.
+    pub fn convert(&mut self, row: &[u8]) -> Vec<Color> {
+        let row = &row[.. self.width as usize];
+        let y = self.row;
+        self.row += 1;
+        let black = |b: bool| if b { Color::Black } else { Color::White };
+        match self.method {
+            Method::Threshold(t) => row.iter().map(|&v| black(v < t)).collect(),
+            Method::Ordered => row.iter().enumerate()
+                .map(|(x, &v)| black(v < BAYER[y % 8][x % 8] * 4 + 2))
+                .collect(),
+            Method::FloydSteinberg => {
+                let [cur, next] = &mut self.errors;
+                let mut out = Vec::with_capacity(row.len());
+                for (x, &v) in row.iter().enumerate() {
+                    // rounded to the nearest value, negative errors as well
+                    let v = v as i32 + (cur[x + 1] + 8).div_euclid(16);
+                    let b = v < 128;
+                    let e = v - if b { 0 } else { 255 };
+                    cur[x + 2] += e * 7;
+                    next[x] += e * 3;
+                    next[x + 1] += e * 5;
+                    next[x + 2] += e;
+                    out.push(black(b));
+                }
+                std::mem::swap(cur, next);
+                next.iter_mut().for_each(|e| *e = 0);
+                out
+            }
+        }
+    }
+}
+
+/// Binarize a grayscale image and encode it.
+///
+/// The image has to be in memory as a whole; use `Binarizer` to convert and encode it row by row.
+pub fn encode_gray(image: &GrayImage, method: Method, coding: Coding) -> Vec<u8> {
+    let mut binarizer = Binarizer::new(image.width, method);
+    let mut encoder = AnyEncoder::new(coding);
+    for row in image.data.chunks(image.width.max(1) as usize) {
+        let pels = binarizer.convert(row);
+        encoder.encode_line(pels.into_iter(), image.width);
+    }
+    encoder.finish()
+}
+
+#[test]
+fn test_dither() {
+    let mut histogram = [0; 256];
+    histogram[20] = 30;
+    histogram[40] = 10;
+    histogram[200] = 50;
+    histogram[230] = 20;
+    let t = otsu(&histogram);
+    assert!(40 < t && t <= 200);
+
+    // a mid gray area comes out half black
+    for method in [Method::FloydSteinberg, Method::Ordered] {
+        let mut binarizer = Binarizer::new(64, method);
+        let black: usize = (0 .. 64)
+            .map(|_| binarizer.convert(&[128; 64]).iter().filter(|&&c| c == Color::Black).count())
+            .sum();
+        assert!((2000 .. 2100).contains(&black), "{:?}: {}", method, black);
+    }
+    // and a dark gray three quarters, without losing the small negative errors
+    let mut binarizer = Binarizer::new(64, Method::FloydSteinberg);
+    let black: usize = (0 .. 64)
+        .map(|_| binarizer.convert(&[64; 64]).iter().filter(|&&c| c == Color::Black).count())
+        .sum();
+    assert!((3060 .. 3080).contains(&black), "{}", black);
+    let mut binarizer = Binarizer::new(3, Method::Threshold(100));
+    assert_eq!(binarizer.convert(&[99, 100, 0]), [Color::Black, Color::White, Color::Black]);
+}
@@ -0,0 +1,191 @@
+/// Size of the coded data in a facsimile data frame, as negotiated in DCS.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FrameSize {
+    Octets64,
+    Octets256,
+}
+impl FrameSize {
+    pub fn octets(self) -> usize {
+        match self {
+            FrameSize::Octets64 => 64,
+            FrameSize::Octets256 => 256,
+        }
+    }
+}
+
+/// Maximum number of frames in a partial page.
+pub const MAX_FRAMES: usize = 256;
+
+/// A facsimile data frame (FCD) without the HDLC framing.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Frame {
+    pub number: u8,
+    pub data: Vec<u8>,
+}
+
+/// A block of up to 256 frames, sent before a PPS.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PartialPage {
+    /// Page counter of the PPS, modulo 256.
+    pub page: u8,
+    /// Block counter of the PPS, modulo 256.
+    pub block: u8,
+    pub frames: Vec<Frame>,
+    /// The last partial page of the page.
+    pub last: bool,
+}
+impl PartialPage {
+    /// The frame count as sent in the PPS, which is the number of frames minus one.
+    pub fn frame_count(&self) -> u8 {
+        (self.frames.len().max(1) - 1) as u8
+    }
+    /// The FIF of the PPS for this partial page.
+    ///
+    /// `post_message` is the FCF of the post message command (such as MPS or EOP) for the
+    /// last partial page, and is replaced by 0 (NULL) for the others.
+    pub fn pps(&self, post_message: u8) -> [u8; 4] {
+        let command = if self.last { post_message } else { 0 };
+        [command, self.page, self.block, self.frame_count()]
+    }
+    /// The frames that are marked in a missing frame map, as received with PPR.
+    pub fn frames_to_resend<'a>(&'a self, missing: &'a [u8; 32]) -> impl Iterator<Item=&'a Frame> + 'a {
+        self.frames.iter().filter(move |f| is_missing(missing, f.number))
+    }
+}
+
+/// Split the coded data of a page into frames and partial pages.
+///
+/// Only the last frame of the page may be shorter than `size`. Empty data gives a single
+/// empty frame, since the PPS always announces at least one.
+pub fn split(data: &[u8], size: FrameSize, page: u8) -> Vec<PartialPage> {
+    let mut chunks: Vec<&[u8]> = data.chunks(size.octets()).collect();
+    if chunks.is_empty() {
+        chunks.push(&[]);
+    }
+    let blocks = chunks.len().div_ceil(MAX_FRAMES).max(1);
+    let mut pages: Vec<PartialPage> = (0 .. blocks).map(|block| PartialPage {
+        page,
+        block: block as u8,
+        frames: vec![],
+        last: block + 1 == blocks,
+    }).collect();
+    for (i, chunk) in chunks.into_iter().enumerate() {
+        pages[i / MAX_FRAMES].frames.push(Frame { number: (i % MAX_FRAMES) as u8, data: chunk.to_vec() });
+    }
+    pages
+}
+
+/// Whether frame `number` is marked in a missing frame map.
+///
+/// Frame `n` is bit `n % 8` (counted from the least significant bit) of octet `n / 8`.
+pub fn is_missing(map: &[u8; 32], number: u8) -> bool {
+    map[number as usize / 8] & (1 << (number % 8)) != 0
+}
+
+/// Collects received frames and joins complete partial pages.
+pub struct Reassembler {
+    size: FrameSize,
+    frames: Vec<Option<Vec<u8>>>,
+    data: Vec<u8>,
+}
+impl Reassembler {
+    pub fn new(size: FrameSize) -> Self {
+        Reassembler { size, frames: vec![None; MAX_FRAMES], data: vec![] }
+    }
+    /// Store a received frame. Frames that are longer than the frame size are rejected
+    /// and will be reported missing.
+    pub fn receive(&mut self, number: u8, data: &[u8]) -> Option<()> {
+        if data.len() > self.size.octets() {
+            return None;
+        }
+        self.frames[number as usize] = Some(data.to_vec());
+        Some(())
+    }
+    /// Handle the end of a partial page, with the frame count of the PPS.
+    ///
+    /// Returns the map of the missing frames, to be sent with PPR. If no frames are missing
+    /// the frames are appended to the data and the next partial page can be received.
+    pub fn end_block(&mut self, frame_count: u8) -> [u8; 32] {
+        let frames = &mut self.frames[..= frame_count as usize];
+        let mut missing = [0; 32];
+        for (n, frame) in frames.iter().enumerate() {
+            if frame.is_none() {
+                missing[n / 8] |= 1 << (n % 8);
+            }
+        }
+        if missing == [0; 32] {
+            for frame in frames.iter_mut() {
+                self.data.extend(frame.take().unwrap());
+            }
+            // frames beyond the count do not belong to this partial page
+            for frame in &mut self.frames {
+                *frame = None;
+            }
+        }
+        missing
+    }
+    /// The data of the complete partial pages so far.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+    pub fn finish(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+#[test]
+fn test_ecm() {
+    use crate::VecWriter;
+    use crate::decoder::decode_g4;
+    use crate::encoder::Encoder;
+
+    let data: Vec<u8> = (0 .. 64 * 300 + 10).map(|i| (i % 251) as u8).collect();
+    let pages = split(&data, FrameSize::Octets64, 3);
+    assert_eq!(pages.len(), 2);
+    assert_eq!(pages[0].pps(0x2E), [0, 3, 0, 255]);
+    assert_eq!(pages[1].pps(0x2E), [0x2E, 3, 1, 44]);
+    assert_eq!(pages[1].frames[44].data.len(), 10);
+
+    let mut reassembler = Reassembler::new(FrameSize::Octets64);
+    for page in &pages {
+        // frames 0 and 9 get lost the first time
+        for frame in &page.frames[1 ..] {
+            if frame.number != 9 {
+                reassembler.receive(frame.number, &frame.data).unwrap();
+            }
+        }
+        let missing = reassembler.end_block(page.frame_count());
+        assert_eq!(&missing[.. 3], &[0b0000_0001, 0b0000_0010, 0]);
+        assert_eq!(page.frames_to_resend(&missing).count(), 2);
+        for frame in page.frames_to_resend(&missing) {
+            reassembler.receive(frame.number, &frame.data).unwrap();
+        }
+        assert_eq!(reassembler.end_block(page.frame_count()), [0; 32]);
+    }
+    assert_eq!(reassembler.finish(), data);
+
+    let empty = split(&[], FrameSize::Octets64, 0);
+    assert_eq!(empty[0].frames, [Frame { number: 0, data: vec![] }]);
+    assert_eq!(empty[0].pps(0x2E), [0x2E, 0, 0, 0]);
+    let mut reassembler = Reassembler::new(FrameSize::Octets64);
+    reassembler.receive(0, &empty[0].frames[0].data).unwrap();
+    assert_eq!(reassembler.end_block(empty[0].frame_count()), [0; 32]);
+    assert_eq!(reassembler.finish(), []);
+
+    let lines = crate::test_lines(200, 400);
+    let mut encoder = Encoder::new(VecWriter::new());
+    for line in &lines {
+        encoder.encode_transitions(line, 400);
+    }
+    let g4 = encoder.finish().finish();
+    let mut reassembler = Reassembler::new(FrameSize::Octets256);
+    for page in split(&g4, FrameSize::Octets256, 0) {
+        for frame in &page.frames {
+            reassembler.receive(frame.number, &frame.data).unwrap();
+        }
+        assert_eq!(reassembler.end_block(page.frame_count()), [0; 32]);
+    }
+    let mut decoded = vec![];
+    decode_g4(reassembler.data().iter().cloned(), 400, None, |line| decoded.push(line.to_vec())).unwrap();
+    assert_eq!(decoded, lines);
+}
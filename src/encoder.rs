@@ -1,4 +1,6 @@
-use crate::{Color, BitWriter, Transitions, maps::{Mode, mode, black, white, EDFB_HALF}};
+use std::time::Duration;
+use crate::{Color, BitWriter, Bits, Transitions, maps::{Mode, mode, black, white, EDFB_HALF, EOL}};
+use crate::runs::normalize_into;
 
 fn absdiff(a: u16, b: u16) -> u16 {
     if a > b {
@@ -25,7 +27,7 @@ fn encode_color(writer: &mut impl BitWriter, color: Color, mut n: u16) {
         //println!("{}", n);
         writer.write(bits);
     };
-    
+
     while n >= 2560 {
         write(2560);
         n -= 2560;
@@ -38,104 +40,137 @@ fn encode_color(writer: &mut impl BitWriter, color: Color, mut n: u16) {
 
     write(n);
 }
-impl<W: BitWriter> Encoder<W> {
-    pub fn new(writer: W) -> Self {
-        Encoder {
-            writer,
-            reference: vec![],
-            current: vec![],
+
+/// Collect the positions of color changes of `pels` into `current`.
+fn collect_transitions(current: &mut Vec<u16>, pels: impl Iterator<Item=Color>) {
+    current.clear();
+    let mut state = Color::White;
+    for (i, c) in pels.enumerate() {
+        if c != state {
+            state = c;
+            current.push(i as u16);
         }
     }
-    pub fn encode_line(&mut self, pels: impl Iterator<Item=Color>, width: u16) {
-        let mut color = Color::White;
-        let mut transitions = Transitions::new(&self.reference);
-        let mut a0 = 0;
-        let mut start_of_row = true;
-        let mut pels = pels.enumerate()
-        .scan(Color::White, |state, (i, c)| {
-            Some(if c != *state {
-                *state = c;
-                Some(i as u16)
-            } else {
-                None
-            })
-        }).filter_map(|x| x);
-        let writer = &mut self.writer;
-        self.current.clear();
-
-        while let Some(a1) = pels.next() {
-            //println!("a1={}", a1);
-            self.current.push(a1);
-            loop {
-                transitions.seek_back(a0);
-                let b1 = transitions.next_color(a0, !color, start_of_row);
-                let b2 = transitions.peek();
-
-                start_of_row = false;
-                //println!("b1={:?}, b2={:?}", b1, b2);
-                match (b1, b2) {
-                    (Some(_b1), Some(b2)) if b2 < a1 => {
-                        //println!("Pass");
-                        let bits = mode::encode(Mode::Pass).unwrap();
-                        writer.write(bits);
-                        transitions.skip(1);
-                        a0 = b2;
-                        continue;
-                    }
-                    (Some(b1), _) if absdiff(a1, b1) <= 3 => {
-                        let delta = a1 as i16 - b1 as i16;
-                        //println!("Vertical({})", delta);
-                        let bits = mode::encode(Mode::Vertical(delta as i8)).unwrap();
-                        writer.write(bits);
-                        a0 = a1;
-                        color = !color;
-                    }
-                    _ => {
-                        let a2 = match pels.next() {
-                            Some(a2) => {
-                                self.current.push(a2);
-                                a2
-                            },
-                            None => width
-                        };
-                        let bits = mode::encode(Mode::Horizontal).unwrap();
-                        writer.write(bits);
-                        let a0a1 = a1 - a0;
-                        let a1a2 = a2 - a1;
-                        //println!("Horizontal({}, {})", a0a1, a1a2);
-                        encode_color(writer, color, a0a1);
-                        encode_color(writer, !color, a1a2);
-                        a0 = a2;
-                    }
-                }
-                break;
-            }
-        }
-        transitions.seek_back(a0);
+}
+
+/// Code one line with Modified Huffman runs.
+pub(crate) fn encode_1d_line(writer: &mut impl BitWriter, current: &[u16], width: u16) {
+    let mut a0 = 0;
+    let mut color = Color::White;
+    for &a1 in current {
+        encode_color(writer, color, a1 - a0);
+        a0 = a1;
+        color = !color;
+    }
+    encode_color(writer, color, width - a0);
+}
+
+/// Code one line relative to the `reference` line with the two-dimensional scheme.
+fn encode_2d_line(writer: &mut impl BitWriter, reference: &[u16], current: &[u16], width: u16) {
+    let mut color = Color::White;
+    let mut transitions = Transitions::new(reference);
+    let mut a0 = 0;
+    let mut start_of_row = true;
+    let mut pels = current.iter().cloned();
+
+    while let Some(a1) = pels.next() {
+        //println!("a1={}", a1);
         loop {
+            transitions.seek_back(a0);
             let b1 = transitions.next_color(a0, !color, start_of_row);
             let b2 = transitions.peek();
+
+            start_of_row = false;
             //println!("b1={:?}, b2={:?}", b1, b2);
-            if let Some(b1) = b1 {
+            match (b1, b2) {
+                (Some(_b1), Some(b2)) if b2 < a1 => {
+                    //println!("Pass");
+                    let bits = mode::encode(Mode::Pass).unwrap();
+                    writer.write(bits);
+                    transitions.skip(1);
+                    a0 = b2;
+                    continue;
+                }
+                (Some(b1), _) if absdiff(a1, b1) <= 3 => {
+                    let delta = a1 as i16 - b1 as i16;
+                    //println!("Vertical({})", delta);
+                    let bits = mode::encode(Mode::Vertical(delta as i8)).unwrap();
+                    writer.write(bits);
+                    a0 = a1;
+                    color = !color;
+                }
+                _ => {
+                    let a2 = pels.next().unwrap_or(width);
+                    let bits = mode::encode(Mode::Horizontal).unwrap();
+                    writer.write(bits);
+                    let a0a1 = a1 - a0;
+                    let a1a2 = a2 - a1;
+                    //println!("Horizontal({}, {})", a0a1, a1a2);
+                    encode_color(writer, color, a0a1);
+                    encode_color(writer, !color, a1a2);
+                    a0 = a2;
+                }
+            }
+            break;
+        }
+    }
+    // no more changes on this line: a1 is at the end of the line
+    transitions.seek_back(a0);
+    loop {
+        let b1 = transitions.next_color(a0, !color, start_of_row);
+        let b2 = transitions.peek();
+        start_of_row = false;
+        //println!("b1={:?}, b2={:?}", b1, b2);
+        match (b1, b2) {
+            (Some(_b1), Some(b2)) => {
                 //println!("Pass");
                 let bits = mode::encode(Mode::Pass).unwrap();
                 writer.write(bits);
                 transitions.skip(1);
-                if let Some(b2) = b2 {
-                    a0 = b2;
-                } else {
-                    a0 = b1;
-                    break;
+                a0 = b2;
+            }
+            (Some(b1), None) if width - b1 <= 3 => {
+                let bits = mode::encode(Mode::Vertical((width - b1) as i8)).unwrap();
+                writer.write(bits);
+                break;
+            }
+            (Some(_b1), None) => {
+                let bits = mode::encode(Mode::Horizontal).unwrap();
+                writer.write(bits);
+                encode_color(writer, color, width - a0);
+                encode_color(writer, !color, 0);
+                break;
+            }
+            (None, _) => {
+                if a0 < width {
+                    //println!("Vertical(0)");
+                    let bits = mode::encode(Mode::Vertical(0)).unwrap();
+                    writer.write(bits);
                 }
-            } else {
                 break;
             }
         }
-        if a0 < width {
-            //println!("Vertical(0)");
-            let bits = mode::encode(Mode::Vertical(0)).unwrap();
-            writer.write(bits);
+    }
+}
+
+impl<W: BitWriter> Encoder<W> {
+    pub fn new(writer: W) -> Self {
+        Encoder {
+            writer,
+            reference: vec![],
+            current: vec![],
         }
+    }
+    pub fn encode_line(&mut self, pels: impl Iterator<Item=Color>, width: u16) {
+        collect_transitions(&mut self.current, pels);
+        encode_2d_line(&mut self.writer, &self.reference, &self.current, width);
+        std::mem::swap(&mut self.reference, &mut self.current);
+    }
+    /// Encode a line given as list of color changes, starting with white
+    /// (as produced by the decoder).
+    pub fn encode_transitions(&mut self, transitions: &[u16], width: u16) {
+        normalize_into(&mut self.current, transitions, width);
+        encode_2d_line(&mut self.writer, &self.reference, &self.current, width);
         std::mem::swap(&mut self.reference, &mut self.current);
     }
     pub fn finish(mut self) -> W {
@@ -143,4 +178,215 @@ impl<W: BitWriter> Encoder<W> {
         self.writer.write(EDFB_HALF);
         self.writer
     }
-}
\ No newline at end of file
+}
+
+/// Counts the bits written through it.
+struct Counter<'a, W> {
+    writer: &'a mut W,
+    bits: u32,
+}
+impl<W: BitWriter> BitWriter for Counter<'_, W> {
+    fn write(&mut self, bits: Bits) {
+        self.bits += bits.len as u32;
+        self.writer.write(bits);
+    }
+}
+
+/// Encoder for Group 3 data with EOL codes, coded one- or two-dimensionally.
+///
+/// The output starts with an EOL and ends with the RTC (six EOLs).
+pub struct G3Encoder<W> {
+    writer: W,
+    /// `None` for one-dimensional coding
+    k: Option<u16>,
+    line: u16,
+    reference: Vec<u16>,
+    current: Vec<u16>,
+    /// minimum number of bits per coded line, including the EOL
+    min_line_bits: u32,
+    /// bits of the current line so far
+    line_bits: u32,
+    bits: u64,
+}
+impl<W: BitWriter> G3Encoder<W> {
+    /// Encoder for one-dimensional (Modified Huffman) coding.
+    pub fn new(writer: W) -> Self {
+        G3Encoder {
+            writer,
+            k: None,
+            line: 0,
+            reference: vec![],
+            current: vec![],
+            min_line_bits: 0,
+            line_bits: 0,
+            bits: 0,
+        }
+    }
+    /// Encoder for two-dimensional (Modified READ) coding,
+    /// where every `k`-th line is coded one-dimensionally.
+    pub fn new_2d(writer: W, k: u16) -> Self {
+        assert!(k > 0);
+        G3Encoder {
+            k: Some(k),
+            ..G3Encoder::new(writer)
+        }
+    }
+    /// Insert fill bits before the EOLs, so that sending each coded line (with its EOL)
+    /// takes at least `ms` milliseconds at `bit_rate` bit/s.
+    pub fn with_min_scan_time(self, bit_rate: u32, ms: u32) -> Self {
+        G3Encoder {
+            min_line_bits: (bit_rate as u64 * ms as u64).div_ceil(1000) as u32,
+            ..self
+        }
+    }
+    fn write(&mut self, bits: Bits) {
+        self.writer.write(bits);
+        self.line_bits += bits.len as u32;
+        self.bits += bits.len as u64;
+    }
+    /// End the current line, padding it to the minimum scan time.
+    fn fill(&mut self) {
+        if self.line_bits > 0 {
+            let mut fill = self.min_line_bits.saturating_sub(self.line_bits);
+            while fill > 0 {
+                let len = fill.min(16) as u8;
+                self.write(Bits { data: 0, len });
+                fill -= len as u32;
+            }
+        }
+        self.line_bits = 0;
+    }
+    fn write_eol(&mut self, one_d: bool) {
+        self.write(EOL);
+        if self.k.is_some() {
+            self.write(Bits { data: one_d as u16, len: 1 });
+        }
+    }
+    fn encode_current(&mut self, width: u16) {
+        let one_d = match self.k {
+            Some(_) => self.line == 0,
+            None => true,
+        };
+        self.fill();
+        self.write_eol(one_d);
+        let mut counter = Counter { writer: &mut self.writer, bits: 0 };
+        if one_d {
+            encode_1d_line(&mut counter, &self.current, width);
+        } else {
+            encode_2d_line(&mut counter, &self.reference, &self.current, width);
+        }
+        let bits = counter.bits;
+        self.line_bits += bits;
+        self.bits += bits as u64;
+        self.line = self.line.wrapping_add(1) % self.k.unwrap_or(1);
+        std::mem::swap(&mut self.reference, &mut self.current);
+    }
+    pub fn encode_line(&mut self, pels: impl Iterator<Item=Color>, width: u16) {
+        collect_transitions(&mut self.current, pels);
+        self.encode_current(width);
+    }
+    /// Encode a line given as list of color changes, starting with white
+    /// (as produced by the decoder).
+    pub fn encode_transitions(&mut self, transitions: &[u16], width: u16) {
+        normalize_into(&mut self.current, transitions, width);
+        self.encode_current(width);
+    }
+    /// Number of bits written so far, including fill bits.
+    pub fn bits(&self) -> u64 {
+        self.bits
+    }
+    /// Estimated time to send the data written so far, and the RTC, at `bit_rate` bit/s.
+    pub fn transmission_time(&self, bit_rate: u32) -> Duration {
+        let rtc = 6 * (EOL.len as u64 + self.k.is_some() as u64);
+        let fill = if self.line_bits > 0 { self.min_line_bits.saturating_sub(self.line_bits) as u64 } else { 0 };
+        Duration::from_secs_f64((self.bits + fill + rtc) as f64 / bit_rate as f64)
+    }
+    /// Write the RTC and return the writer.
+    pub fn finish(mut self) -> W {
+        self.fill();
+        for _ in 0 .. 6 {
+            self.write_eol(true);
+        }
+        self.writer
+    }
+}
+
+#[test]
+fn test_min_scan_time() {
+    use std::time::Duration;
+    use crate::VecWriter;
+    use crate::decoder::decode_g3_checked;
+
+    // white lines take 29 bits, the busy ones more than 192
+    let lines: Vec<Vec<u16>> = (0 .. 40).map(|y| match y % 4 {
+        0 => (1 .. 60).map(|x| x * 3 + y).collect(),
+        _ => vec![],
+    }).collect();
+    for &two_d in &[false, true] {
+        let mut plain = if two_d { G3Encoder::new_2d(VecWriter::new(), 2) } else { G3Encoder::new(VecWriter::new()) };
+        // 20 ms at 9600 bit/s
+        let mut encoder = if two_d { G3Encoder::new_2d(VecWriter::new(), 2) } else { G3Encoder::new(VecWriter::new()) }
+            .with_min_scan_time(9600, 20);
+        for line in &lines {
+            plain.encode_transitions(line, 1728);
+            encoder.encode_transitions(line, 1728);
+        }
+        assert!(plain.bits() < encoder.bits());
+        let time = encoder.transmission_time(9600);
+        assert!(time >= Duration::from_millis(40 * 20) && time < Duration::from_millis(40 * 21));
+        let bits = encoder.bits();
+        let data = encoder.finish().finish();
+        // the estimate covers everything but the final padding to a byte boundary
+        let estimated = (time.as_secs_f64() * 9600.0).round() as usize;
+        assert!(bits as usize <= estimated && estimated <= data.len() * 8 && data.len() * 8 < estimated + 8);
+
+        let mut decoded = vec![];
+        let stats = decode_g3_checked(data.iter().cloned(), 1728, two_d, |line| decoded.push(line.to_vec()));
+        assert_eq!(stats.bad_lines, 0);
+        assert_eq!(decoded, lines);
+    }
+}
+
+#[test]
+fn test_2d_edge_cases() {
+    use crate::VecWriter;
+    use crate::decoder::{decode_g4, pels};
+
+    // pairs of reference and coded line, at a width of 100
+    let cases: &[(&[u16], &[u16])] = &[
+        // a line starting with black, below a line starting with black
+        (&[0, 10], &[0, 12]),
+        (&[], &[0, 5]),
+        // pass mode at the start of the line
+        (&[2, 4, 50, 60], &[30, 40]),
+        (&[0, 4, 50, 60], &[30, 40]),
+        // a change in the reference line near or far from the end, after the last change
+        (&[10, 20, 95], &[10, 20]),
+        (&[10, 20, 50], &[10, 20]),
+        (&[50], &[]),
+        (&[98], &[]),
+        // black up to the end of the line
+        (&[50], &[60]),
+        (&[10, 20, 30], &[90]),
+    ];
+    for &(reference, line) in cases {
+        let mut encoder = Encoder::new(VecWriter::new());
+        encoder.encode_line(pels(reference, 100), 100);
+        encoder.encode_line(pels(line, 100), 100);
+        let data = encoder.finish().finish();
+        let mut lines = vec![];
+        decode_g4(data.iter().cloned(), 100, None, |l| lines.push(l.to_vec())).unwrap();
+        assert_eq!(lines, [reference, line], "reference {:?}", reference);
+    }
+
+    // horizontal mode with the first run up to the end of the line
+    let mut writer = VecWriter::new();
+    writer.write(mode::encode(Mode::Horizontal).unwrap());
+    encode_color(&mut writer, Color::White, 100);
+    encode_color(&mut writer, Color::Black, 0);
+    writer.write(EDFB_HALF);
+    writer.write(EDFB_HALF);
+    let mut lines = vec![];
+    decode_g4(writer.finish().iter().cloned(), 100, None, |l| lines.push(l.to_vec())).unwrap();
+    assert_eq!(lines, [vec![]]);
+}
@@ -0,0 +1,191 @@
+use crate::{BitReader, BitWriter, Bits, ByteReader, Resolution, VecWriter};
+use crate::decoder::{decode_g3_checked, LineStats};
+use crate::hdlc::BitOrder;
+use crate::maps::EOL;
+
+/// What comes before the coded data.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Wrapper {
+    /// Nothing, as in the `.g3` files of mgetty.
+    Plain,
+    /// The 64 byte header of Digifax and GammaLink files, as written by efax.
+    Digifax { page: u16, fine: bool },
+}
+
+/// How a raw Group 3 file is stored.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Format {
+    pub wrapper: Wrapper,
+    /// Order of the bits in each byte. Files written from fax modem data usually
+    /// have the least significant bit first.
+    pub bit_order: BitOrder,
+    /// Whether the data starts with an EOL, which is optional.
+    pub leading_eol: bool,
+    /// Two-dimensional (MR) coding, where every EOL is followed by a tag bit.
+    pub two_d: bool,
+}
+
+/// A raw Group 3 file, as found by `read`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct G3File {
+    pub format: Format,
+    /// The page width that decodes best.
+    pub width: u16,
+    /// The resolution, if the file says.
+    pub resolution: Option<Resolution>,
+    /// The coded data, most significant bit first and starting with an EOL
+    /// (as expected by `decoder::decode_g3`).
+    pub data: Vec<u8>,
+}
+impl G3File {
+    /// Decode the page, as in `decoder::decode_g3_checked`.
+    pub fn decode(&self, line_cb: impl FnMut(&[u16])) -> LineStats {
+        decode_g3_checked(self.data.iter().cloned(), self.width, self.format.two_d, line_cb)
+    }
+}
+
+const DIGIFAX_HEADER_LEN: usize = 64;
+const DIGIFAX_MAGIC: &[u8] = b"\0PC Research, Inc";
+/// Widths tried when reading, the fax page widths at 204 and 408 dpi.
+const WIDTHS: [u16; 6] = [1728, 2048, 2432, 3456, 4096, 4864];
+/// Number of bytes decoded to find out the format.
+const SAMPLE_LEN: usize = 8192;
+
+/// Copy the bits of `data` after the first `skip`, with `prefix` in front.
+///
+/// Trailing zero bytes are dropped, so the padding does not grow; the data ends with an RTC anyway.
+fn shift_bits(data: &[u8], skip: usize, prefix: Option<Bits>) -> Vec<u8> {
+    let mut reader = ByteReader::from_slice(data.get(skip / 8 ..).unwrap_or_default());
+    reader.consume((skip % 8) as u8);
+    let mut writer = VecWriter::with_capacity(data.len() * 8 + 16);
+    if let Some(bits) = prefix {
+        writer.write(bits);
+    }
+    loop {
+        let len = if reader.peek(8).is_some() { 8 } else { 1 };
+        match reader.peek(len) {
+            Some(data) => writer.write(Bits { data, len }),
+            None => break,
+        }
+        reader.consume(len);
+    }
+    let mut out = writer.finish();
+    while out.last() == Some(&0) {
+        out.pop();
+    }
+    out
+}
+
+fn leading_zeros(data: &[u8]) -> usize {
+    match data.iter().position(|&b| b != 0) {
+        Some(i) => i * 8 + data[i].leading_zeros() as usize,
+        None => data.len() * 8,
+    }
+}
+
+/// Find out how a raw Group 3 file is stored and normalize its data.
+///
+/// The bit order, the coding and the width are guessed by decoding the start of the
+/// data in every way and taking the one with the most good lines.
+pub fn read(data: &[u8]) -> Option<G3File> {
+    let (wrapper, coded) = match data.get(.. DIGIFAX_MAGIC.len()) {
+        Some(magic) if magic == DIGIFAX_MAGIC && data.len() >= DIGIFAX_HEADER_LEN => {
+            let page = u16::from_le_bytes([data[24], data[25]]);
+            (Wrapper::Digifax { page, fine: data[29] != 0 }, &data[DIGIFAX_HEADER_LEN ..])
+        }
+        _ => (Wrapper::Plain, data),
+    };
+
+    let mut best: Option<(u32, Format, u16, Vec<u8>)> = None;
+    for &bit_order in &[BitOrder::MsbFirst, BitOrder::LsbFirst] {
+        let data: Vec<u8> = match bit_order {
+            BitOrder::MsbFirst => coded.to_vec(),
+            BitOrder::LsbFirst => coded.iter().map(|b| b.reverse_bits()).collect(),
+        };
+        let leading_eol = leading_zeros(&data) >= 11;
+        let data = if leading_eol { data } else { shift_bits(&data, 0, Some(EOL)) };
+        let sample = &data[.. data.len().min(SAMPLE_LEN)];
+        for &two_d in &[false, true] {
+            for &width in &WIDTHS {
+                let stats = decode_g3_checked(sample.iter().cloned(), width, two_d, |_| {});
+                let good = stats.lines - stats.bad_lines;
+                if best.as_ref().is_none_or(|b| good > b.0) {
+                    let format = Format { wrapper, bit_order, leading_eol, two_d };
+                    best = Some((good, format, width, data.clone()));
+                }
+            }
+        }
+    }
+    let (good, format, width, data) = best?;
+    if good == 0 {
+        return None;
+    }
+    let resolution = match wrapper {
+        Wrapper::Plain => None,
+        Wrapper::Digifax { fine: true, .. } => Some(Resolution::FINE),
+        Wrapper::Digifax { fine: false, .. } => Some(Resolution::STANDARD),
+    };
+    Some(G3File { format, width, resolution, data })
+}
+
+/// Store Group 3 data, as written by `encoder::G3Encoder`, in the given format.
+pub fn write(data: &[u8], format: &Format) -> Vec<u8> {
+    let mut out = vec![];
+    if let Wrapper::Digifax { page, fine } = format.wrapper {
+        out.extend_from_slice(DIGIFAX_MAGIC);
+        out.resize(DIGIFAX_HEADER_LEN, 0);
+        out[24 .. 26].copy_from_slice(&page.to_le_bytes());
+        out[29] = fine as u8;
+    }
+    let start = out.len();
+    let has_eol = leading_zeros(data) >= 11;
+    match (has_eol, format.leading_eol) {
+        (true, false) => out.extend(shift_bits(data, leading_zeros(data) + 1, None)),
+        (false, true) => out.extend(shift_bits(data, 0, Some(EOL))),
+        _ => out.extend_from_slice(data),
+    }
+    if format.bit_order == BitOrder::LsbFirst {
+        for b in &mut out[start ..] {
+            *b = b.reverse_bits();
+        }
+    }
+    out
+}
+
+#[test]
+fn test_g3file() {
+    use crate::decoder::decode_g3;
+    use crate::encoder::G3Encoder;
+
+    let lines = crate::test_lines(120, 2048);
+    for &two_d in &[false, true] {
+        let mut encoder = if two_d { G3Encoder::new_2d(VecWriter::new(), 2) } else { G3Encoder::new(VecWriter::new()) };
+        for line in &lines {
+            encoder.encode_transitions(line, 2048);
+        }
+        let coded = encoder.finish().finish();
+        for &wrapper in &[Wrapper::Plain, Wrapper::Digifax { page: 3, fine: true }] {
+            for &bit_order in &[BitOrder::MsbFirst, BitOrder::LsbFirst] {
+                for &leading_eol in &[true, false] {
+                    let format = Format { wrapper, bit_order, leading_eol, two_d };
+                    let data = write(&coded, &format);
+                    let file = read(&data).unwrap();
+                    assert_eq!((file.format, file.width), (format, 2048));
+                    assert_eq!(file.resolution.is_some(), wrapper != Wrapper::Plain);
+                    let mut decoded = vec![];
+                    let stats = file.decode(|line| decoded.push(line.to_vec()));
+                    assert_eq!(stats.bad_lines, 0);
+                    assert_eq!(decoded, lines);
+                    if !two_d {
+                        if leading_eol {
+                            assert_eq!(file.data, coded);
+                        }
+                        assert!(decode_g3(file.data.iter().cloned(), |_| {}).is_some());
+                    }
+                    assert_eq!(write(&file.data, &file.format), data);
+                }
+            }
+        }
+    }
+    assert_eq!(read(b"\0PC Research, Inc"), None);
+}
@@ -0,0 +1,214 @@
+use crate::{BitReader, BitWriter, Bits, ByteReader, VecWriter};
+
+/// The flag separating frames, `01111110`.
+pub const FLAG: u8 = 0x7E;
+
+/// Order in which the bits of an octet are sent on the line.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BitOrder {
+    /// Least significant bit first, as HDLC specifies.
+    LsbFirst,
+    MsbFirst,
+}
+
+/// The frame check sequence: CRC-16 of ITU-T V.42 (reflected polynomial 0x8408,
+/// initial value and final XOR 0xFFFF). It is sent low octet first.
+pub fn fcs(data: &[u8]) -> u16 {
+    !data.iter().fold(0xFFFF, |mut crc: u16, &b| {
+        crc ^= b as u16;
+        for _ in 0 .. 8 {
+            crc = if crc & 1 != 0 { crc >> 1 ^ 0x8408 } else { crc >> 1 };
+        }
+        crc
+    })
+}
+
+/// Writes frames with zero bit insertion.
+pub struct HdlcEncoder<W> {
+    writer: W,
+    order: BitOrder,
+    ones: u8,
+}
+impl<W: BitWriter> HdlcEncoder<W> {
+    pub fn new(writer: W, order: BitOrder) -> Self {
+        HdlcEncoder { writer, order, ones: 0 }
+    }
+    /// Write `n` flags. At least one has to come before the first frame.
+    pub fn flags(&mut self, n: usize) {
+        for _ in 0 .. n {
+            self.writer.write(Bits { data: FLAG as u16, len: 8 });
+        }
+        self.ones = 0;
+    }
+    fn write_byte(&mut self, byte: u8) {
+        for i in 0 .. 8 {
+            let bit = match self.order {
+                BitOrder::LsbFirst => byte >> i & 1,
+                BitOrder::MsbFirst => byte >> (7 - i) & 1,
+            };
+            self.writer.write(Bits { data: bit as u16, len: 1 });
+            if bit == 1 {
+                self.ones += 1;
+                if self.ones == 5 {
+                    self.writer.write(Bits { data: 0, len: 1 });
+                    self.ones = 0;
+                }
+            } else {
+                self.ones = 0;
+            }
+        }
+    }
+    /// Write the frame `data` followed by its FCS and a closing flag.
+    pub fn frame(&mut self, data: &[u8]) {
+        for &b in data {
+            self.write_byte(b);
+        }
+        let fcs = fcs(data);
+        self.write_byte(fcs as u8);
+        self.write_byte((fcs >> 8) as u8);
+        self.flags(1);
+    }
+    pub fn finish(self) -> W {
+        self.writer
+    }
+}
+
+/// Reads frames, removing inserted zero bits and checking the FCS.
+pub struct HdlcDecoder<R> {
+    reader: R,
+    order: BitOrder,
+    synced: bool,
+    bad_frames: u32,
+}
+impl<R: BitReader> HdlcDecoder<R> {
+    pub fn new(reader: R, order: BitOrder) -> Self {
+        HdlcDecoder { reader, order, synced: false, bad_frames: 0 }
+    }
+    fn bit(&mut self) -> Option<u8> {
+        let bit = self.reader.peek(1)? as u8;
+        self.reader.consume(1);
+        Some(bit)
+    }
+    /// Skip everything up to and including the next flag.
+    fn sync(&mut self) -> Option<()> {
+        let mut last = 0u8;
+        while last != FLAG {
+            last = last << 1 | self.bit()?;
+        }
+        Some(())
+    }
+    /// The next frame with a correct FCS, which is removed.
+    ///
+    /// Frames with a wrong FCS, a length that is not a multiple of eight bits,
+    /// or ended by an abort (seven ones) are skipped and counted in `bad_frames`.
+    pub fn next_frame(&mut self) -> Option<Vec<u8>> {
+        if !self.synced {
+            self.sync()?;
+            self.synced = true;
+        }
+        let mut frame = vec![];
+        let mut byte = 0u8;
+        let mut bits = 0;
+        let mut ones = 0;
+        loop {
+            let bit = self.bit()?;
+            if bit == 1 {
+                ones += 1;
+                if ones == 7 {
+                    // abort (or an idle line), wait for the next flag
+                    if !frame.is_empty() {
+                        self.bad_frames += 1;
+                    }
+                    self.sync()?;
+                    frame.clear();
+                    (byte, bits, ones) = (0, 0, 0);
+                    continue;
+                }
+            } else {
+                if ones == 5 {
+                    // inserted zero
+                    ones = 0;
+                    continue;
+                }
+                if ones == 6 {
+                    // a flag; its zero and six ones were taken as data
+                    let complete = bits == 7 && frame.len() >= 2;
+                    if complete {
+                        let (data, check) = frame.split_at(frame.len() - 2);
+                        if fcs(data).to_le_bytes() == check {
+                            frame.truncate(frame.len() - 2);
+                            return Some(frame);
+                        }
+                    }
+                    if !frame.is_empty() {
+                        self.bad_frames += 1;
+                    }
+                    frame.clear();
+                    (byte, bits, ones) = (0, 0, 0);
+                    continue;
+                }
+                ones = 0;
+            }
+            match self.order {
+                BitOrder::LsbFirst => byte |= bit << bits,
+                BitOrder::MsbFirst => byte |= bit << (7 - bits),
+            }
+            bits += 1;
+            if bits == 8 {
+                frame.push(byte);
+                byte = 0;
+                bits = 0;
+            }
+        }
+    }
+    /// Number of frames that were skipped so far.
+    pub fn bad_frames(&self) -> u32 {
+        self.bad_frames
+    }
+}
+
+/// Encode frames into bytes, starting with `preamble` flags.
+pub fn encode<F: AsRef<[u8]>>(frames: impl IntoIterator<Item=F>, order: BitOrder, preamble: usize) -> Vec<u8> {
+    let mut encoder = HdlcEncoder::new(VecWriter::new(), order);
+    encoder.flags(preamble.max(1));
+    for frame in frames {
+        encoder.frame(frame.as_ref());
+    }
+    encoder.finish().finish()
+}
+
+/// Decode all frames with a correct FCS from bytes.
+pub fn decode(data: &[u8], order: BitOrder) -> Vec<Vec<u8>> {
+    let mut decoder = HdlcDecoder::new(ByteReader::from_slice(data), order);
+    std::iter::from_fn(|| decoder.next_frame()).collect()
+}
+
+#[test]
+fn test_hdlc() {
+    assert_eq!(fcs(b"123456789"), 0x906E);
+    let frames: Vec<Vec<u8>> = vec![vec![0xFF, 0x13, 0x80, 0x7E, 0xFF, 0xFF, 0x00], vec![0xFF, 0x03, 0x06, 0x00, 0x3F]];
+    for &order in &[BitOrder::LsbFirst, BitOrder::MsbFirst] {
+        let data = encode(&frames, order, 3);
+        assert_eq!(&data[.. 3], &[FLAG; 3]);
+        assert_eq!(decode(&data, order), frames);
+        // only the closing flags contain six ones in a row
+        let bits: String = data[3 ..].iter().map(|b| format!("{:08b}", b)).collect();
+        assert_eq!(bits.matches("111111").count(), frames.len());
+    }
+
+    // noise before the first flag, a damaged frame, and an aborted one
+    let mut encoder = HdlcEncoder::new(VecWriter::new(), BitOrder::LsbFirst);
+    encoder.writer.write(Bits { data: 0b1011_0111_0011, len: 12 });
+    encoder.flags(1);
+    encoder.frame(&frames[0]);
+    encoder.writer.write(Bits { data: 0b0101_0101, len: 8 });
+    encoder.writer.write(Bits { data: 0xFF, len: 8 });
+    encoder.flags(1);
+    encoder.frame(&frames[1]);
+    let mut data = encoder.finish().finish();
+    data[3] ^= 0x10;
+    let mut decoder = HdlcDecoder::new(ByteReader::from_slice(&data), BitOrder::LsbFirst);
+    assert_eq!(decoder.next_frame(), Some(frames[1].clone()));
+    assert_eq!(decoder.next_frame(), None);
+    assert_eq!(decoder.bad_frames(), 2);
+}
@@ -0,0 +1,658 @@
+use std::convert::TryFrom;
+use crate::{Color, VecWriter};
+use crate::bitmap::{fill_spans, packed_line};
+use crate::decoder::decode_g4;
+use crate::encoder::Encoder;
+
+const ESC: u8 = 0xFF;
+const STUFF: u8 = 0x00;
+const SDNORM: u8 = 0x02;
+const SDRST: u8 = 0x03;
+const ABORT: u8 = 0x04;
+const NEWLEN: u8 = 0x05;
+const ATMOVE: u8 = 0x06;
+const COMMENT: u8 = 0x07;
+
+// option flags of the header
+const LRLTWO: u8 = 0x40;
+const VLENGTH: u8 = 0x20;
+const TPBON: u8 = 0x08;
+const DPON: u8 = 0x04;
+const DPPRIV: u8 = 0x02;
+const DPLAST: u8 = 0x01;
+
+/// Contexts of the typical prediction pseudo pel, for the two- and three-line template.
+const TPB2CX: usize = 0x195;
+const TPB3CX: usize = 0x0E5;
+
+/// Probability estimation of the QM coder (T.82 table 24):
+/// Qe, next state after an MPS, next state after an LPS, and whether an LPS swaps the MPS.
+const QE: [(u16, u8, u8, bool); 113] = [
+    (0x5A1D, 1, 1, true), (0x2586, 2, 14, false), (0x1114, 3, 16, false), (0x080B, 4, 18, false),
+    (0x03D8, 5, 20, false), (0x01DA, 6, 23, false), (0x00E5, 7, 25, false), (0x006F, 8, 28, false),
+    (0x0036, 9, 30, false), (0x001A, 10, 33, false), (0x000D, 11, 35, false), (0x0006, 12, 9, false),
+    (0x0003, 13, 10, false), (0x0001, 13, 12, false), (0x5A7F, 15, 15, true), (0x3F25, 16, 36, false),
+    (0x2CF2, 17, 38, false), (0x207C, 18, 39, false), (0x17B9, 19, 40, false), (0x1182, 20, 42, false),
+    (0x0CEF, 21, 43, false), (0x09A1, 22, 45, false), (0x072F, 23, 46, false), (0x055C, 24, 48, false),
+    (0x0406, 25, 49, false), (0x0303, 26, 51, false), (0x0240, 27, 52, false), (0x01B1, 28, 54, false),
+    (0x0144, 29, 56, false), (0x00F5, 30, 57, false), (0x00B7, 31, 59, false), (0x008A, 32, 60, false),
+    (0x0068, 33, 62, false), (0x004E, 34, 63, false), (0x003B, 35, 32, false), (0x002C, 9, 33, false),
+    (0x5AE1, 37, 37, true), (0x484C, 38, 64, false), (0x3A0D, 39, 65, false), (0x2EF1, 40, 67, false),
+    (0x261F, 41, 68, false), (0x1F33, 42, 69, false), (0x19A8, 43, 70, false), (0x1518, 44, 72, false),
+    (0x1177, 45, 73, false), (0x0E74, 46, 74, false), (0x0BFB, 47, 75, false), (0x09F8, 48, 77, false),
+    (0x0861, 49, 78, false), (0x0706, 50, 79, false), (0x05CD, 51, 48, false), (0x04DE, 52, 50, false),
+    (0x040F, 53, 50, false), (0x0363, 54, 51, false), (0x02D4, 55, 52, false), (0x025C, 56, 53, false),
+    (0x01F8, 57, 54, false), (0x01A4, 58, 55, false), (0x0160, 59, 56, false), (0x0125, 60, 57, false),
+    (0x00F6, 61, 58, false), (0x00CB, 62, 59, false), (0x00AB, 63, 61, false), (0x008F, 32, 61, false),
+    (0x5B12, 65, 65, true), (0x4D04, 66, 80, false), (0x412C, 67, 81, false), (0x37D8, 68, 82, false),
+    (0x2FE8, 69, 83, false), (0x293C, 70, 84, false), (0x2379, 71, 86, false), (0x1EDF, 72, 87, false),
+    (0x1AA9, 73, 87, false), (0x174E, 74, 72, false), (0x1424, 75, 72, false), (0x119C, 76, 74, false),
+    (0x0F6B, 77, 74, false), (0x0D51, 78, 75, false), (0x0BB6, 79, 77, false), (0x0A40, 48, 77, false),
+    (0x5832, 81, 80, true), (0x4D1C, 82, 88, false), (0x438E, 83, 89, false), (0x3BDD, 84, 90, false),
+    (0x34EE, 85, 91, false), (0x2EAE, 86, 92, false), (0x299A, 87, 93, false), (0x2516, 71, 86, false),
+    (0x5570, 89, 88, true), (0x4CA9, 90, 95, false), (0x44D9, 91, 96, false), (0x3E22, 92, 97, false),
+    (0x3824, 93, 99, false), (0x32B4, 94, 99, false), (0x2E17, 86, 93, false), (0x56A8, 96, 95, true),
+    (0x4F46, 97, 101, false), (0x47E5, 98, 102, false), (0x41CF, 99, 103, false), (0x3C3D, 100, 104, false),
+    (0x375E, 93, 99, false), (0x5231, 102, 105, false), (0x4C0F, 103, 106, false), (0x4639, 104, 107, false),
+    (0x415E, 99, 103, false), (0x5627, 106, 105, true), (0x50E7, 107, 108, false), (0x4B85, 103, 109, false),
+    (0x5597, 109, 110, false), (0x504F, 107, 111, false), (0x5A10, 111, 110, true), (0x5522, 109, 112, false),
+    (0x59EB, 111, 112, true),
+];
+
+/// Move the state of a context (the MPS in the top bit, the index into `QE` below) on.
+fn update(state: &mut u8, lps: bool) {
+    let (_, nmps, nlps, switch) = QE[(*state & 0x7F) as usize];
+    *state = if lps {
+        (*state & 0x80) ^ if switch { 0x80 } else { 0 } | nlps
+    } else {
+        *state & 0x80 | nmps
+    };
+}
+
+/// Append a byte of coded data, stuffing a zero after 0xFF.
+fn emit(out: &mut Vec<u8>, byte: u8) {
+    out.push(byte);
+    if byte == ESC {
+        out.push(STUFF);
+    }
+}
+
+/// Add a carry to the byte held back by the encoder.
+///
+/// It cannot overflow: bytes of 0xFF are counted in `sc` instead of being held back, and
+/// after a carry `c >> 19` is below 0x120, so the byte held back is below 0x20.
+fn carry(byte: u8) -> u8 {
+    byte.checked_add(1).expect("carry into a held back 0xFF byte")
+}
+
+/// The QM arithmetic encoder.
+struct ArithEncoder {
+    c: u32,
+    a: u32,
+    /// Number of 0xFF bytes held back, as a carry may still turn them into zeros.
+    sc: u32,
+    ct: u8,
+    buffer: Option<u8>,
+}
+impl ArithEncoder {
+    fn new() -> Self {
+        ArithEncoder { c: 0, a: 0x10000, sc: 0, ct: 11, buffer: None }
+    }
+    fn encode(&mut self, states: &mut [u8], cx: usize, pel: bool, out: &mut Vec<u8>) {
+        let state = &mut states[cx];
+        let qe = QE[(*state & 0x7F) as usize].0 as u32;
+        self.a -= qe;
+        if pel != (*state & 0x80 != 0) {
+            // unless the LPS interval is the larger one, code the LPS
+            if self.a >= qe {
+                self.c += self.a;
+                self.a = qe;
+            }
+            update(state, true);
+        } else {
+            if self.a >= 0x8000 {
+                return;
+            }
+            if self.a < qe {
+                self.c += self.a;
+                self.a = qe;
+            }
+            update(state, false);
+        }
+
+        while self.a < 0x8000 {
+            self.a <<= 1;
+            self.c <<= 1;
+            self.ct -= 1;
+            if self.ct == 0 {
+                self.byte_out(out);
+            }
+        }
+    }
+    fn byte_out(&mut self, out: &mut Vec<u8>) {
+        let temp = self.c >> 19;
+        if temp > 0xFF {
+            // the carry propagates through the held back bytes
+            if let Some(b) = self.buffer {
+                emit(out, carry(b));
+            }
+            for _ in 0 .. self.sc {
+                out.push(0);
+            }
+            self.sc = 0;
+            self.buffer = Some(temp as u8);
+        } else if temp == 0xFF {
+            self.sc += 1;
+        } else {
+            if let Some(b) = self.buffer {
+                emit(out, b);
+            }
+            for _ in 0 .. self.sc {
+                emit(out, 0xFF);
+            }
+            self.sc = 0;
+            self.buffer = Some(temp as u8);
+        }
+        self.c &= 0x7FFFF;
+        self.ct = 8;
+    }
+    /// Terminate the coded data, leaving out trailing zero bytes.
+    fn flush(mut self, out: &mut Vec<u8>) {
+        // pick the value in the interval with the most trailing zeros
+        let temp = (self.a - 1 + self.c) & 0xFFFF_0000;
+        self.c = if temp < self.c { temp + 0x8000 } else { temp };
+        self.c <<= self.ct;
+        if self.c & 0xF800_0000 != 0 {
+            if let Some(b) = self.buffer {
+                emit(out, carry(b));
+            }
+            if self.c & 0x07FF_F800 != 0 {
+                for _ in 0 .. self.sc {
+                    out.push(0);
+                }
+            }
+        } else {
+            if let Some(b) = self.buffer {
+                emit(out, b);
+            }
+            for _ in 0 .. self.sc {
+                emit(out, 0xFF);
+            }
+        }
+        if self.c & 0x07FF_F800 != 0 {
+            emit(out, (self.c >> 19) as u8);
+            if self.c & 0x0007_F800 != 0 {
+                emit(out, (self.c >> 11) as u8);
+            }
+        }
+    }
+}
+
+/// The QM arithmetic decoder, reading the (stuffed) data of one stripe.
+struct ArithDecoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+    c: u64,
+    a: u64,
+    /// Negative once the data is used up and zeros are shifted in.
+    ct: i8,
+    startup: bool,
+}
+impl<'a> ArithDecoder<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        ArithDecoder { data, pos: 0, c: 0, a: 1, ct: 0, startup: true }
+    }
+    fn decode(&mut self, states: &mut [u8], cx: usize) -> bool {
+        while self.a < 0x8000 || self.startup {
+            while (0 ..= 8).contains(&self.ct) {
+                match self.data.get(self.pos) {
+                    Some(&ESC) if self.data.get(self.pos + 1) == Some(&STUFF) => {
+                        self.c |= 0xFF << (8 - self.ct);
+                        self.ct += 8;
+                        self.pos += 2;
+                    }
+                    Some(&ESC) | None => self.ct = -1,
+                    Some(&b) => {
+                        self.c |= (b as u64) << (8 - self.ct);
+                        self.ct += 8;
+                        self.pos += 1;
+                    }
+                }
+            }
+            self.c <<= 1;
+            self.a <<= 1;
+            if self.ct >= 0 {
+                self.ct -= 1;
+            }
+            if self.a == 0x10000 {
+                self.startup = false;
+            }
+        }
+
+        let state = &mut states[cx];
+        let qe = QE[(*state & 0x7F) as usize].0 as u64;
+        let mps = *state & 0x80 != 0;
+        self.a -= qe;
+        let lps = if (self.c >> 16) < self.a {
+            if self.a >= 0x8000 {
+                return mps;
+            }
+            self.a < qe
+        } else {
+            self.c -= self.a << 16;
+            let lps = self.a >= qe;
+            self.a = qe;
+            lps
+        };
+        update(state, lps);
+        mps != lps
+    }
+}
+
+/// Coding options of a JBIG image, as far as the T.85 profile allows them.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Options {
+    /// Use the two-line template instead of the three-line template.
+    pub two_line: bool,
+    /// Code lines equal to the previous one with a single bit (TPGDON).
+    pub typical_prediction: bool,
+    /// Number of lines per stripe.
+    pub stripe_lines: u32,
+    /// Position of the adaptive template pel: `x` pels to the left on the current line,
+    /// or its default position on the line above if 0. Must be 0 or between 3 and 127.
+    pub at_x: u8,
+}
+impl Default for Options {
+    fn default() -> Self {
+        Options { two_line: false, typical_prediction: true, stripe_lines: 128, at_x: 0 }
+    }
+}
+
+/// The bi-level image header (BIH) of a JBIG image.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Header {
+    pub width: u32,
+    /// Height of the image. Once decoded, it includes changes by NEWLEN markers.
+    pub height: u32,
+    /// The height may be reduced by a NEWLEN marker.
+    pub variable_length: bool,
+    pub options: Options,
+}
+
+/// The pels the context of a pel is made of.
+struct Template {
+    two_line: bool,
+    at_x: u8,
+}
+impl Template {
+    /// Context of pel `x` of `rows[0]`, where `rows[1]` and `rows[2]` are the lines above.
+    fn context(&self, rows: &[Vec<u8>; 3], x: usize) -> usize {
+        fn pel(row: &[u8], x: isize) -> usize {
+            if x < 0 {
+                return 0;
+            }
+            let x = x as usize;
+            row.get(x / 8).map_or(0, |&b| (b >> (7 - x % 8) & 1) as usize)
+        }
+        let [current, above, above2] = rows;
+        let x = x as isize;
+        let at = if self.at_x == 0 { pel(above, x + 2) } else { pel(current, x - self.at_x as isize) };
+        if self.two_line {
+            pel(above, x - 3) << 9 | pel(above, x - 2) << 8 | pel(above, x - 1) << 7 | pel(above, x) << 6
+                | pel(above, x + 1) << 5 | at << 4
+                | pel(current, x - 4) << 3 | pel(current, x - 3) << 2 | pel(current, x - 2) << 1 | pel(current, x - 1)
+        } else {
+            pel(above2, x - 1) << 9 | pel(above2, x) << 8 | pel(above2, x + 1) << 7
+                | pel(above, x - 2) << 6 | pel(above, x - 1) << 5 | pel(above, x) << 4 | pel(above, x + 1) << 3 | at << 2
+                | pel(current, x - 2) << 1 | pel(current, x - 1)
+        }
+    }
+    fn typical_context(&self) -> usize {
+        if self.two_line { TPB2CX } else { TPB3CX }
+    }
+}
+
+/// Encoder for JBIG (T.85) images, taking the lines one by one.
+pub struct JbigEncoder {
+    width: u16,
+    height: Option<u32>,
+    options: Options,
+    template: Template,
+    /// The current line and the two above.
+    rows: [Vec<u8>; 3],
+    states: Vec<u8>,
+    coder: ArithEncoder,
+    typical: bool,
+    lines: u32,
+    out: Vec<u8>,
+}
+impl JbigEncoder {
+    /// If the `height` is not known in advance, it is sent in a NEWLEN marker by `finish`.
+    pub fn new(width: u16, height: Option<u32>, options: Options) -> Self {
+        let stride = (width as usize).div_ceil(8);
+        let mut out = vec![0, 0, 1, 0];
+        out.extend_from_slice(&(width as u32).to_be_bytes());
+        out.extend_from_slice(&height.unwrap_or(u32::MAX).to_be_bytes());
+        out.extend_from_slice(&options.stripe_lines.max(1).to_be_bytes());
+        let mut flags = 0;
+        if options.two_line {
+            flags |= LRLTWO;
+        }
+        if height.is_none() {
+            flags |= VLENGTH;
+        }
+        if options.typical_prediction {
+            flags |= TPBON;
+        }
+        out.extend_from_slice(&[options.at_x.max(8), 0, 0, flags]);
+        if options.at_x != 0 {
+            out.extend_from_slice(&[ESC, ATMOVE, 0, 0, 0, 0, options.at_x, 0]);
+        }
+        JbigEncoder {
+            width,
+            height,
+            options,
+            template: Template { two_line: options.two_line, at_x: options.at_x },
+            rows: [vec![0; stride], vec![0; stride], vec![0; stride]],
+            states: vec![0; 1024],
+            coder: ArithEncoder::new(),
+            typical: false,
+            lines: 0,
+            out,
+        }
+    }
+    pub fn encode_line(&mut self, pels: impl Iterator<Item=Color>) {
+        self.next_row();
+        for (x, pel) in pels.take(self.width as usize).enumerate() {
+            if pel == Color::Black {
+                self.rows[0][x / 8] |= 0x80 >> (x % 8);
+            }
+        }
+        self.encode_row();
+    }
+    pub fn encode_transitions(&mut self, transitions: &[u16]) {
+        self.next_row();
+        fill_spans(&mut self.rows[0], transitions, self.width);
+        self.encode_row();
+    }
+    fn next_row(&mut self) {
+        self.rows.rotate_right(1);
+        for b in &mut self.rows[0] {
+            *b = 0;
+        }
+    }
+    fn encode_row(&mut self) {
+        // lines beyond the announced height are dropped
+        if self.height.is_some_and(|h| self.lines >= h) {
+            return;
+        }
+        let mut coded = true;
+        if self.options.typical_prediction {
+            let typical = self.rows[0] == self.rows[1];
+            let cx = self.template.typical_context();
+            self.coder.encode(&mut self.states, cx, typical == self.typical, &mut self.out);
+            self.typical = typical;
+            coded = !typical;
+        }
+        if coded {
+            for x in 0 .. self.width as usize {
+                let cx = self.template.context(&self.rows, x);
+                let pel = self.rows[0][x / 8] & (0x80 >> (x % 8)) != 0;
+                self.coder.encode(&mut self.states, cx, pel, &mut self.out);
+            }
+        }
+        self.lines += 1;
+        if self.lines.is_multiple_of(self.options.stripe_lines.max(1)) {
+            self.end_stripe();
+        }
+    }
+    fn end_stripe(&mut self) {
+        std::mem::replace(&mut self.coder, ArithEncoder::new()).flush(&mut self.out);
+        self.out.extend_from_slice(&[ESC, SDNORM]);
+    }
+    /// Fill up the image to the announced height with white lines and return the data.
+    pub fn finish(mut self) -> Vec<u8> {
+        if let Some(height) = self.height {
+            while self.lines < height {
+                self.encode_transitions(&[]);
+            }
+        }
+        if !self.lines.is_multiple_of(self.options.stripe_lines.max(1)) {
+            self.end_stripe();
+        }
+        if self.height.is_none() {
+            self.out.extend_from_slice(&[ESC, NEWLEN]);
+            self.out.extend_from_slice(&self.lines.to_be_bytes());
+        }
+        self.out
+    }
+}
+
+fn u32_at(data: &[u8], pos: usize) -> Option<u32> {
+    data.get(pos .. pos + 4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+/// Read the header of a JBIG image. Only single layer images of one plane are supported.
+pub fn read_header(data: &[u8]) -> Option<Header> {
+    let bih = data.get(.. 20)?;
+    if bih[0 .. 4] != [0, 0, 1, 0] || bih[17] != 0 {
+        return None;
+    }
+    let stripe_lines = u32_at(bih, 12)?;
+    if stripe_lines == 0 {
+        return None;
+    }
+    let flags = bih[19];
+    Some(Header {
+        width: u32_at(bih, 4)?,
+        height: u32_at(bih, 8)?,
+        variable_length: flags & VLENGTH != 0,
+        options: Options {
+            two_line: flags & LRLTWO != 0,
+            typical_prediction: flags & TPBON != 0,
+            stripe_lines,
+            at_x: 0,
+        },
+    })
+}
+
+/// Stripes and the floating marker segments between them.
+enum Entity<'a> {
+    /// Coded data of a stripe, and whether the probabilities are reset after it.
+    Stripe(&'a [u8], bool),
+    AtMove { y: u32, x: u8 },
+    NewLength(u32),
+}
+
+fn entities(data: &[u8]) -> Option<Vec<Entity<'_>>> {
+    let mut entities = vec![];
+    let mut pos = 0;
+    while pos < data.len() {
+        if data[pos] == ESC && matches!(data.get(pos + 1), Some(&(ABORT ..= COMMENT))) {
+            match data[pos + 1] {
+                ATMOVE => {
+                    // only horizontal moves are allowed
+                    if *data.get(pos + 7)? != 0 {
+                        return None;
+                    }
+                    entities.push(Entity::AtMove { y: u32_at(data, pos + 2)?, x: data[pos + 6] });
+                    pos += 8;
+                }
+                NEWLEN => {
+                    entities.push(Entity::NewLength(u32_at(data, pos + 2)?));
+                    pos += 6;
+                }
+                COMMENT => pos += 6 + u32_at(data, pos + 2)? as usize,
+                _ => return None,
+            }
+            continue;
+        }
+        let start = pos;
+        while !(data[pos] == ESC && *data.get(pos + 1)? != STUFF) {
+            pos += if data[pos] == ESC { 2 } else { 1 };
+            if pos >= data.len() {
+                return None;
+            }
+        }
+        let reset = match data[pos + 1] {
+            SDNORM => false,
+            SDRST => true,
+            _ => return None,
+        };
+        entities.push(Entity::Stripe(&data[start .. pos], reset));
+        pos += 2;
+    }
+    Some(entities)
+}
+
+/// Decode a JBIG image.
+///
+/// The callback `line_cb` is called for each decoded line, given as list of color changes
+/// just as by `decoder::decode_g4`. Returns the header, with the final height.
+pub fn decode(data: &[u8], mut line_cb: impl FnMut(&[u16])) -> Option<Header> {
+    let mut header = read_header(data)?;
+    let width = u16::try_from(header.width).ok()?;
+    let flags = data[19];
+    let mut pos = 20;
+    if flags & (DPON | DPPRIV | DPLAST) == DPON | DPPRIV {
+        // a private deterministic prediction table, which is of no use for a single layer
+        pos += 1728;
+    }
+    let entities = entities(data.get(pos ..)?)?;
+    for entity in &entities {
+        if let Entity::NewLength(height) = *entity {
+            header.height = header.height.min(height);
+        }
+    }
+
+    let stride = (width as usize).div_ceil(8);
+    let mut rows = [vec![0; stride], vec![0; stride], vec![0; stride]];
+    let mut template = Template { two_line: header.options.two_line, at_x: 0 };
+    let mut states = vec![0; 1024];
+    let mut at_moves = vec![];
+    let mut typical = false;
+    let mut y = 0;
+    for entity in entities {
+        let (pscd, reset) = match entity {
+            Entity::Stripe(pscd, reset) => (pscd, reset),
+            Entity::AtMove { y, x } => {
+                at_moves.push((y, x));
+                continue;
+            }
+            Entity::NewLength(_) => continue,
+        };
+        let mut coder = ArithDecoder::new(pscd);
+        for _ in 0 .. header.options.stripe_lines {
+            if y >= header.height {
+                break;
+            }
+            if let Some(&(_, x)) = at_moves.iter().rev().find(|&&(at_y, _)| at_y == y) {
+                template.at_x = x;
+                header.options.at_x = x;
+            }
+            rows.rotate_right(1);
+            let mut coded = true;
+            if header.options.typical_prediction {
+                typical ^= !coder.decode(&mut states, template.typical_context());
+                coded = !typical;
+            }
+            if coded {
+                rows[0].iter_mut().for_each(|b| *b = 0);
+                for x in 0 .. width as usize {
+                    let cx = template.context(&rows, x);
+                    if coder.decode(&mut states, cx) {
+                        rows[0][x / 8] |= 0x80 >> (x % 8);
+                    }
+                }
+            } else {
+                let (current, above) = rows.split_at_mut(1);
+                current[0].copy_from_slice(&above[0]);
+            }
+            line_cb(&packed_line(&rows[0], width));
+            y += 1;
+        }
+        if reset {
+            states.iter_mut().for_each(|s| *s = 0);
+            typical = false;
+        }
+    }
+    if y < header.height {
+        return None;
+    }
+    Some(header)
+}
+
+/// Convert a JBIG image into Group 4 data. Returns the data and the width.
+pub fn to_g4(data: &[u8]) -> Option<(Vec<u8>, u16)> {
+    let width = u16::try_from(read_header(data)?.width).ok()?;
+    let mut encoder = Encoder::new(VecWriter::new());
+    decode(data, |line| encoder.encode_transitions(line, width))?;
+    Some((encoder.finish().finish(), width))
+}
+
+/// Convert Group 4 data into a JBIG image. See `decoder::decode_g4` for `height`.
+pub fn from_g4(input: &[u8], width: u16, height: Option<u16>, options: Options) -> Option<Vec<u8>> {
+    let mut encoder = JbigEncoder::new(width, height.map(u32::from), options);
+    decode_g4(input.iter().cloned(), width, height, |line| encoder.encode_transitions(line))?;
+    Some(encoder.finish())
+}
+
+#[test]
+fn test_jbig() {
+    use crate::bitmap::Bitmap;
+    use crate::decoder::pels;
+    let lines: Vec<Vec<u16>> = (0 .. 40u16).map(|y| match y % 7 {
+        0 | 1 => vec![],
+        2 => vec![y, y + 10, 60, 99],
+        3 => vec![0, 1, 2, 3, 50],
+        _ => (0 .. y / 2).map(|i| i * 5 + y % 3).collect(),
+    }).collect();
+    let bitmap = Bitmap::from_lines(&lines, 99);
+
+    let configs = [
+        (Some(40), Options::default()),
+        (None, Options { two_line: true, typical_prediction: false, stripe_lines: 16, at_x: 5 }),
+        (None, Options { stripe_lines: 8, ..Options::default() }),
+    ];
+    for &(height, options) in &configs {
+        let mut encoder = JbigEncoder::new(99, height, options);
+        for line in &lines {
+            encoder.encode_line(pels(line, 99));
+        }
+        let data = encoder.finish();
+        let mut decoded = Bitmap::empty(99);
+        let header = decode(&data, |line| decoded.push_line(line)).unwrap();
+        assert_eq!(header.height, 40);
+        assert_eq!(header.variable_length, height.is_none());
+        assert_eq!(header.options, options);
+        assert_eq!(decoded, bitmap);
+    }
+
+    let mut encoder = Encoder::new(VecWriter::new());
+    for line in &lines {
+        encoder.encode_transitions(line, 99);
+    }
+    let g4 = encoder.finish().finish();
+    let jbig = from_g4(&g4, 99, Some(40), Options::default()).unwrap();
+    assert_eq!(to_g4(&jbig), Some((g4, 99)));
+
+    // a 24x10 image coded by the JBIG-KIT reference implementation with the same options
+    let rows = [
+        0xe0, 0x01, 0xc0, 0x88, 0x81, 0x11, 0x8c, 0x15, 0x00, 0x9e, 0x49, 0x24, 0x8f, 0x03, 0x02,
+        0xef, 0x81, 0xc0, 0x8f, 0xc1, 0x11, 0x8f, 0xf5, 0x00, 0x9f, 0xf9, 0x24, 0x8f, 0xfb, 0x02,
+    ];
+    let bie = [
+        0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x18, 0x00, 0x00, 0x00, 0x0a, 0x00, 0x00, 0x00, 0x80,
+        0x08, 0x00, 0x00, 0x08, 0xff, 0x00, 0xb8, 0x01, 0x7b, 0x1f, 0x9b, 0xb9, 0x55, 0x90, 0xb8, 0xe6,
+        0xb2, 0x1e, 0x16, 0x25, 0x72, 0x12, 0x50, 0x73, 0x36, 0xa2, 0x1f, 0x46, 0x9c, 0xe0, 0xff, 0x02,
+    ];
+    let bitmap = Bitmap { width: 24, height: 10, data: rows.to_vec() };
+    let mut decoded = Bitmap::empty(24);
+    assert_eq!(decode(&bie, |line| decoded.push_line(line)).map(|h| h.height), Some(10));
+    assert_eq!(decoded, bitmap);
+    let mut encoder = JbigEncoder::new(24, Some(10), Options::default());
+    for line in bitmap.lines() {
+        encoder.encode_transitions(&line);
+    }
+    assert_eq!(encoder.finish(), bie);
+}
@@ -0,0 +1,336 @@
+use std::convert::TryFrom;
+use crate::{ByteReader, Resolution};
+use crate::bitmap::{fill_spans, Bitmap};
+use crate::runs;
+use crate::decoder::decode_2d_line;
+
+/// Decode the data of a generic region coded with MMR (T.6).
+///
+/// Exactly `height` lines are passed to `line_cb`. Unlike in `decoder::decode_g4` the
+/// end-of-block marker is optional; if the data ends early the remaining lines are white.
+pub fn decode_generic_mmr(data: &[u8], width: u16, height: u32, mut line_cb: impl FnMut(&[u16])) -> Option<()> {
+    let mut reader = ByteReader::new(data.iter().cloned());
+    let mut reference: Vec<u16> = vec![];
+    let mut current: Vec<u16> = vec![];
+    let mut y = 0;
+    while y < height {
+        if !decode_2d_line(&mut reader, &reference, &mut current, width)? {
+            break;
+        }
+        line_cb(&current);
+        std::mem::swap(&mut reference, &mut current);
+        current.clear();
+        y += 1;
+    }
+    for _ in y .. height {
+        line_cb(&[]);
+    }
+    Some(())
+}
+
+const FILE_ID: &[u8] = b"\x97JB2\r\n\x1A\n";
+
+const PAGE_INFORMATION: u8 = 48;
+const END_OF_PAGE: u8 = 49;
+const END_OF_STRIPE: u8 = 50;
+const END_OF_FILE: u8 = 51;
+/// Intermediate, immediate and immediate lossless generic regions.
+const GENERIC_REGIONS: [u8; 3] = [36, 38, 39];
+/// Region segments of other kinds, which cannot be decoded.
+const OTHER_REGIONS: [u8; 9] = [4, 6, 7, 20, 22, 23, 40, 42, 43];
+
+struct Segment<'a> {
+    kind: u8,
+    data: &'a [u8],
+}
+
+fn u32_at(data: &[u8], pos: usize) -> Option<u32> {
+    let b = data.get(pos .. pos + 4)?;
+    Some(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+/// Parse a segment header at `pos`. Returns the header and its length in bytes, with the
+/// length of the data.
+fn segment_header(data: &[u8], pos: usize) -> Option<(u8, usize, u32)> {
+    let number = u32_at(data, pos)?;
+    let flags = *data.get(pos + 4)?;
+    let mut p = pos + 5;
+    let count_byte = *data.get(p)?;
+    let count = if count_byte >> 5 == 7 {
+        let count = u32_at(data, p)? & 0x1FFF_FFFF;
+        p += 4 + (count as usize + 8) / 8;
+        count
+    } else {
+        p += 1;
+        (count_byte >> 5) as u32
+    };
+    let number_size = match number {
+        0 ..= 256 => 1,
+        257 ..= 65536 => 2,
+        _ => 4,
+    };
+    p += count as usize * number_size;
+    p += if flags & 0x40 != 0 { 4 } else { 1 };
+    let length = u32_at(data, p)?;
+    Some((flags & 0x3F, p + 4 - pos, length))
+}
+
+/// Split a sequence of segments.
+///
+/// In random-access files all headers come first, then the data.
+fn segments(data: &[u8], random_access: bool) -> Option<Vec<Segment<'_>>> {
+    let mut headers = vec![];
+    let mut pos = 0;
+    let mut segments = vec![];
+    while pos < data.len() {
+        let (kind, header_len, length) = segment_header(data, pos)?;
+        // an unknown length is only allowed for generic regions, which we cannot delimit without decoding
+        if length == u32::MAX {
+            return None;
+        }
+        pos += header_len;
+        if random_access {
+            headers.push((kind, length as usize));
+        } else {
+            segments.push(Segment { kind, data: data.get(pos .. pos + length as usize)? });
+            pos += length as usize;
+        }
+        if kind == END_OF_FILE {
+            break;
+        }
+    }
+    for (kind, length) in headers {
+        segments.push(Segment { kind, data: data.get(pos .. pos + length)? });
+        pos += length;
+    }
+    Some(segments)
+}
+
+/// A decoded page.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Page {
+    pub bitmap: Bitmap,
+    pub resolution: Option<Resolution>,
+}
+
+/// How a region is combined with the page.
+#[derive(Copy, Clone)]
+enum Op {
+    Or,
+    And,
+    Xor,
+    Xnor,
+    Replace,
+}
+impl Op {
+    fn new(n: u8) -> Option<Op> {
+        Some(match n {
+            0 => Op::Or,
+            1 => Op::And,
+            2 => Op::Xor,
+            3 => Op::Xnor,
+            4 => Op::Replace,
+            _ => return None,
+        })
+    }
+    /// Combine eight pels of the page and of the region.
+    fn apply(self, page: u8, region: u8) -> u8 {
+        match self {
+            Op::Or => page | region,
+            Op::And => page & region,
+            Op::Xor => page ^ region,
+            Op::Xnor => !(page ^ region),
+            Op::Replace => region,
+        }
+    }
+}
+
+struct PageState {
+    bitmap: Bitmap,
+    resolution: Option<Resolution>,
+    default_pixel: bool,
+    /// the page height is given by end of stripe segments
+    striped: bool,
+    /// the operator used for all regions, or `None` if each region gives its own
+    op: Option<Op>,
+}
+impl PageState {
+    fn new(data: &[u8]) -> Option<PageState> {
+        let width = u16::try_from(u32_at(data, 0)?).ok()?;
+        let height = u32_at(data, 4)?;
+        let (x, y) = (u32_at(data, 8)?, u32_at(data, 12)?);
+        let flags = *data.get(16)?;
+        let striped = height == u32::MAX;
+        let height = if striped { 0 } else { u16::try_from(height).ok()? };
+        let dpi = |ppm: u32| ((ppm as u64 * 254 + 5000) / 10000).min(u16::MAX as u64) as u16;
+        let default_pixel = flags & 0x04 != 0;
+        let mut bitmap = Bitmap::new(width, height);
+        if default_pixel {
+            bitmap.invert();
+        }
+        Some(PageState {
+            bitmap,
+            resolution: if x > 0 && y > 0 { Some(Resolution { x: dpi(x), y: dpi(y) }) } else { None },
+            default_pixel,
+            striped,
+            // unless regions may override it, the page operator is used
+            op: if flags & 0x40 != 0 { None } else { Op::new((flags >> 3) & 3) },
+        })
+    }
+    fn grow(&mut self, height: u16) {
+        while self.bitmap.height < height {
+            self.bitmap.push_line(if self.default_pixel { &[0] } else { &[] });
+        }
+    }
+    fn region(&mut self, data: &[u8]) -> Option<()> {
+        let width = u16::try_from(u32_at(data, 0)?).ok()?;
+        let height = u32_at(data, 4)?;
+        let x = u32_at(data, 8)?;
+        let y = u32_at(data, 12)?;
+        let op = match self.op {
+            Some(op) => op,
+            None => Op::new(*data.get(16)? & 7)?,
+        };
+        let flags = *data.get(17)?;
+        // only MMR coding is supported
+        if flags & 1 == 0 {
+            return None;
+        }
+        if self.striped {
+            self.grow(u16::try_from(y as u64 + height as u64).ok()?);
+        }
+        let page = &mut self.bitmap;
+        let page_width = page.width;
+        // the pels of the page covered by the region
+        let x = x.min(page_width as u32) as u16;
+        let end = (x as u32 + width as u32).min(page_width as u32) as u16;
+        let mut mask = vec![0; page.stride()];
+        fill_spans(&mut mask, &[x, end], page_width);
+        let mut placed = vec![0; page.stride()];
+        let mut row = y;
+        decode_generic_mmr(&data[18 ..], width, height, |line| {
+            if row < page.height as u32 {
+                let line = runs::shift(&runs::resize(line, width, page_width), page_width, x as i32);
+                placed.iter_mut().for_each(|b| *b = 0);
+                fill_spans(&mut placed, &line, page_width);
+                for ((p, &r), &m) in page.row_mut(row as u16).iter_mut().zip(&placed).zip(&mask) {
+                    *p = *p & !m | op.apply(*p, r) & m;
+                }
+            }
+            row += 1;
+        })
+    }
+}
+
+/// Decode the pages of a sequence of segments, all of which belong to the same file.
+///
+/// Regions that are not generic MMR regions make the decoding fail, other segments
+/// such as dictionaries or tables are ignored.
+fn decode_segments<'a>(segments: impl IntoIterator<Item=Segment<'a>>) -> Option<Vec<Page>> {
+    let mut pages = vec![];
+    let mut page: Option<PageState> = None;
+    for segment in segments {
+        match segment.kind {
+            PAGE_INFORMATION => {
+                if let Some(p) = page.take() {
+                    pages.push(Page { bitmap: p.bitmap, resolution: p.resolution });
+                }
+                page = Some(PageState::new(segment.data)?);
+            }
+            k if GENERIC_REGIONS.contains(&k) => page.as_mut()?.region(segment.data)?,
+            k if OTHER_REGIONS.contains(&k) => return None,
+            END_OF_STRIPE => {
+                let p = page.as_mut()?;
+                if p.striped {
+                    let end = u32_at(segment.data, 0)?;
+                    p.grow(u16::try_from(end as u64 + 1).ok()?);
+                }
+            }
+            END_OF_PAGE => if let Some(p) = page.take() {
+                pages.push(Page { bitmap: p.bitmap, resolution: p.resolution });
+            },
+            END_OF_FILE => break,
+            _ => {}
+        }
+    }
+    if let Some(p) = page.take() {
+        pages.push(Page { bitmap: p.bitmap, resolution: p.resolution });
+    }
+    Some(pages)
+}
+
+/// Decode a stream embedded in a PDF file with `/Filter /JBIG2Decode`, together with the
+/// optional `/JBIG2Globals` stream.
+pub fn decode_embedded(data: &[u8], globals: Option<&[u8]>) -> Option<Page> {
+    let mut all = match globals {
+        Some(globals) => segments(globals, false)?,
+        None => vec![],
+    };
+    all.extend(segments(data, false)?);
+    decode_segments(all)?.into_iter().next()
+}
+
+/// Decode a JBIG2 file, in sequential or random-access organisation.
+pub fn decode_file(data: &[u8]) -> Option<Vec<Page>> {
+    if data.get(.. 8)? != FILE_ID {
+        return None;
+    }
+    let flags = *data.get(8)?;
+    // the number of pages is only present if known
+    let start = if flags & 2 == 0 { 13 } else { 9 };
+    let random_access = flags & 1 == 0;
+    decode_segments(segments(data.get(start ..)?, random_access)?)
+}
+
+#[test]
+fn test_jbig2() {
+    use crate::{VecWriter, encoder::Encoder};
+
+    let lines: Vec<Vec<u16>> = vec![vec![2, 5], vec![], vec![0, 8]];
+    let mut encoder = Encoder::new(VecWriter::new());
+    for line in &lines {
+        encoder.encode_transitions(line, 8);
+    }
+    let mmr = encoder.finish().finish();
+
+    let segment = |number: u32, kind: u8, data: &[u8]| {
+        let mut s = number.to_be_bytes().to_vec();
+        s.extend_from_slice(&[kind, 0, 1]);
+        s.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        s.extend_from_slice(data);
+        s
+    };
+    // 10 x 5 page, striped with unknown height, OR as default operator
+    let page = [0, 0, 0, 10, 0xFF, 0xFF, 0xFF, 0xFF, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x80, 5];
+    let mut region = vec![0, 0, 0, 8, 0, 0, 0, 3, 0, 0, 0, 1, 0, 0, 0, 1, 0, 1];
+    // cut off the end-of-block marker
+    region.extend_from_slice(&mmr[.. mmr.len() - 3]);
+    let mut data = segment(0, PAGE_INFORMATION, &page);
+    data.extend(segment(1, 38, &region));
+    data.extend(segment(2, END_OF_STRIPE, &[0, 0, 0, 4]));
+    data.extend(segment(3, END_OF_PAGE, &[]));
+
+    let page = decode_embedded(&data, None).unwrap();
+    assert_eq!(page.resolution, None);
+    let expected: Vec<Vec<u16>> = vec![vec![], vec![3, 6], vec![], vec![1, 9], vec![]];
+    assert_eq!(page.bitmap.lines().collect::<Vec<_>>(), expected);
+
+    let mut file = FILE_ID.to_vec();
+    file.extend_from_slice(&[1, 0, 0, 0, 1]);
+    file.extend(data);
+    assert_eq!(decode_file(&file).unwrap(), [page]);
+
+    // regions with their own operators: OR, XOR, REPLACE and AND, partly outside of the page
+    let region = |x: u8, y: u8, op: u8| {
+        let mut region = vec![0, 0, 0, 8, 0, 0, 0, 3, 0, 0, 0, x, 0, 0, 0, y, op, 1];
+        region.extend_from_slice(&mmr);
+        region
+    };
+    let page = [0, 0, 0, 10, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0x40, 0, 0];
+    let mut data = segment(0, PAGE_INFORMATION, &page);
+    for (i, &(x, y, op)) in [(0, 0, 0), (4, 0, 2), (1, 1, 4), (2, 0, 1)].iter().enumerate() {
+        data.extend(segment(i as u32 + 1, 38, &region(x, y, op)));
+    }
+    let page = decode_embedded(&data, None).unwrap();
+    assert_eq!(page.bitmap.lines().collect::<Vec<_>>(), [vec![4, 5, 6, 7], vec![], vec![0, 1, 9]]);
+}
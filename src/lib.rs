@@ -12,6 +12,77 @@ pub mod encoder;
 /// TIFF helper functions
 pub mod tiff;
 
+/// Conversion between Group 3 and Group 4 coding
+pub mod transcode;
+
+/// Operations on lines given as list of color changes
+///
+/// All functions take a line as list of color changes, starting with white, as produced
+/// by the decoder and accepted by `Encoder::encode_transitions`, together with the width
+/// of the line. Positions at or beyond the width and pairs of changes at the same position
+/// are ignored. The returned lists are strictly increasing and within the new width.
+pub mod runs;
+
+/// Packed bi-level images
+pub mod bitmap;
+
+/// Rotating and flipping pages
+pub mod transform;
+
+/// Conversion between fax resolutions
+pub mod resample;
+
+/// Scaling pages by arbitrary ratios
+pub mod scale;
+
+/// Grayscale previews of pages
+pub mod preview;
+
+/// Thresholding and dithering of grayscale images
+pub mod dither;
+
+/// Reading and writing PBM and PGM images
+pub mod netpbm;
+
+/// Writing PNG images
+pub mod png;
+
+/// Reading and writing monochrome BMP images
+pub mod bmp;
+
+/// Writing fax pages as PDF and extracting them again
+pub mod pdf;
+
+/// Decoding JBIG2 generic regions coded with MMR
+pub mod jbig2;
+
+/// Coding pages as JBIG (T.85)
+pub mod jbig;
+
+/// Framing for the error correction mode (T.4 Annex A)
+pub mod ecm;
+
+/// HDLC framing of T.30 and ECM frames
+pub mod hdlc;
+
+/// T.30 capability frames (DIS, DTC and DCS)
+pub mod t30;
+
+/// T.30 sessions without I/O
+pub mod session;
+
+/// T.38 IFP packets and UDPTL framing
+pub mod t38;
+
+/// Reading and writing Structured Fax Files (SFF) of CAPI
+pub mod sff;
+
+/// Raw Group 3 files, plain or with a Digifax header
+pub mod g3file;
+
+/// CALS Type I raster files (MIL-R-28002)
+pub mod cals;
+
 /// Trait used to read data bitwise.
 /// 
 /// For lazy people `ByteReader` is provided which implements this trait.
@@ -192,6 +263,36 @@ impl Not for Color {
     }
 }
 
+/// Coding schemes for bi-level image data.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Coding {
+    /// Group 3, one-dimensional (Modified Huffman, ITU-T T.4)
+    Group3OneD,
+    /// Group 3, two-dimensional (Modified READ, ITU-T T.4).
+    ///
+    /// Every `k`-th line is coded one-dimensionally.
+    Group3TwoD { k: u16 },
+    /// Group 4 (Modified Modified READ, ITU-T T.6)
+    Group4,
+}
+
+/// Horizontal and vertical resolution in dots per inch.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Resolution {
+    pub x: u16,
+    pub y: u16,
+}
+impl Resolution {
+    /// 8 pels/mm x 3.85 lines/mm
+    pub const STANDARD: Resolution = Resolution { x: 204, y: 98 };
+    /// 8 pels/mm x 7.7 lines/mm
+    pub const FINE: Resolution = Resolution { x: 204, y: 196 };
+    /// 8 pels/mm x 15.4 lines/mm
+    pub const SUPERFINE: Resolution = Resolution { x: 204, y: 391 };
+    /// 16 pels/mm x 15.4 lines/mm
+    pub const ULTRAFINE: Resolution = Resolution { x: 408, y: 391 };
+}
+
 struct Transitions<'a> {
     edges: &'a [u16],
     pos: usize
@@ -211,11 +312,10 @@ impl<'a> Transitions<'a> {
     }
     fn next_color(&mut self, start: u16, color: Color, start_of_row: bool) -> Option<u16> {
         if start_of_row {
-            if color == Color::Black {
-                return self.edges.get(0).cloned()
-            } else {
-                return self.edges.get(1).cloned()
-            }
+            // a0 is in front of the first pel, so a change at 0 counts
+            let idx = if color == Color::Black { 0 } else { 1 };
+            self.pos = self.edges.len().min(idx + 1);
+            return self.edges.get(idx).cloned()
         }
         while self.pos < self.edges.len() {
             if self.edges[self.pos] <= start {
@@ -265,3 +365,24 @@ impl fmt::Debug for Bits {
         write!(f, "d={:0b} w={}", self.data, self.len)
     }
 }
+
+/// Lines with varied content for the tests, as list of color changes: white and black
+/// lines, lines starting with black or ending with black, many short runs and edges that
+/// move a little from line to line. `width` has to be at least 64.
+#[cfg(test)]
+pub(crate) fn test_lines(height: u16, width: u16) -> Vec<Vec<u16>> {
+    let edges = |y: u16| {
+        let a = y % (width / 4);
+        vec![a, a + width / 4, a + width / 2, width - 1]
+    };
+    (0 .. height).map(|y| match y % 8 {
+        0 => vec![],
+        1 => vec![0],
+        2 => vec![0, 3 + y % 7],
+        3 => vec![width - 1 - y % 5],
+        4 => (1 .. width / 8).map(|i| i * 8 + (y + i) % 3).collect(),
+        5 => edges(y),
+        6 => edges(y - 1),
+        _ => vec![width / 3, width / 3 * 2],
+    }).collect()
+}
@@ -0,0 +1,228 @@
+use std::convert::TryInto;
+use crate::bitmap::Bitmap;
+
+/// Bitmaps (PBM) or graymaps (PGM).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Kind {
+    Bitmap,
+    Graymap,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Header {
+    pub kind: Kind,
+    /// ASCII (`P1`, `P2`) instead of binary (`P4`, `P5`) samples
+    pub plain: bool,
+    pub width: u16,
+    pub height: u16,
+    /// Largest gray value, 1 for bitmaps.
+    pub maxval: u16,
+}
+
+/// One image of a Netpbm file.
+#[derive(Clone, Debug)]
+pub struct Image<'a> {
+    pub header: Header,
+    raster: &'a [u8],
+}
+
+struct Cursor<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+impl<'a> Cursor<'a> {
+    /// Skip whitespace and comments.
+    fn skip_space(&mut self) {
+        while let Some(&b) = self.data.get(self.pos) {
+            match b {
+                b'#' => while self.data.get(self.pos).is_some_and(|&b| b != b'\n' && b != b'\r') {
+                    self.pos += 1;
+                },
+                b if b.is_ascii_whitespace() => self.pos += 1,
+                _ => break,
+            }
+        }
+    }
+    fn number(&mut self) -> Option<u32> {
+        self.skip_space();
+        let start = self.pos;
+        while self.data.get(self.pos).is_some_and(|b| b.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        std::str::from_utf8(&self.data[start .. self.pos]).ok()?.parse().ok()
+    }
+    /// A single digit of a plain bitmap, which need not be separated by whitespace.
+    fn bit(&mut self) -> Option<u16> {
+        self.skip_space();
+        let b = match self.data.get(self.pos)? {
+            b'0' => 0,
+            b'1' => 1,
+            _ => return None,
+        };
+        self.pos += 1;
+        Some(b)
+    }
+    fn header(&mut self) -> Option<Header> {
+        let (kind, plain) = match self.data.get(self.pos .. self.pos + 2)? {
+            b"P1" => (Kind::Bitmap, true),
+            b"P2" => (Kind::Graymap, true),
+            b"P4" => (Kind::Bitmap, false),
+            b"P5" => (Kind::Graymap, false),
+            _ => return None,
+        };
+        self.pos += 2;
+        let width = self.number()?.try_into().ok()?;
+        let height = self.number()?.try_into().ok()?;
+        let maxval = match kind {
+            Kind::Bitmap => 1,
+            Kind::Graymap => self.number()?.try_into().ok().filter(|&m| m > 0)?,
+        };
+        if !plain {
+            // a single whitespace character separates the header from the samples
+            if !self.data.get(self.pos)?.is_ascii_whitespace() {
+                return None;
+            }
+            self.pos += 1;
+        }
+        Some(Header { kind, plain, width, height, maxval })
+    }
+    /// The samples of the next row; 1 is black for bitmaps, 0 is black for graymaps.
+    fn row(&mut self, header: &Header) -> Option<Vec<u16>> {
+        let width = header.width as usize;
+        let row = match (header.kind, header.plain) {
+            (Kind::Bitmap, true) => (0 .. width).map(|_| self.bit()).collect::<Option<_>>()?,
+            (Kind::Graymap, true) => (0 .. width)
+                .map(|_| self.number().and_then(|n| n.try_into().ok()))
+                .collect::<Option<_>>()?,
+            (Kind::Bitmap, false) => {
+                let bytes = self.data.get(self.pos .. self.pos + width.div_ceil(8))?;
+                self.pos += bytes.len();
+                (0 .. width).map(|x| (bytes[x / 8] >> (7 - x % 8) & 1) as u16).collect()
+            }
+            (Kind::Graymap, false) => {
+                let size = if header.maxval > 255 { 2 } else { 1 };
+                let bytes = self.data.get(self.pos .. self.pos + width * size)?;
+                self.pos += bytes.len();
+                bytes.chunks(size).map(|b| b.iter().fold(0, |v, &b| v << 8 | b as u16)).collect()
+            }
+        };
+        Some(row)
+    }
+}
+
+/// Read all images of a Netpbm file. Only bitmaps and graymaps are supported.
+pub fn read(data: &[u8]) -> Option<Vec<Image<'_>>> {
+    let mut cursor = Cursor { data, pos: 0 };
+    let mut images = vec![];
+    loop {
+        cursor.skip_space();
+        if cursor.pos == data.len() {
+            break;
+        }
+        let header = cursor.header()?;
+        let start = cursor.pos;
+        match (header.kind, header.plain) {
+            (Kind::Bitmap, false) => cursor.pos += (header.width as usize).div_ceil(8) * header.height as usize,
+            (Kind::Graymap, false) => cursor.pos += header.width as usize * header.height as usize * if header.maxval > 255 { 2 } else { 1 },
+            // the length of plain samples is only known after parsing them
+            _ => for _ in 0 .. header.height {
+                cursor.row(&header)?;
+            }
+        }
+        if cursor.pos > data.len() {
+            return None;
+        }
+        images.push(Image { header, raster: &data[start .. cursor.pos] });
+    }
+    Some(images)
+}
+
+impl<'a> Image<'a> {
+    fn rows(&self) -> impl Iterator<Item=Vec<u16>> + 'a {
+        let header = self.header;
+        let mut cursor = Cursor { data: self.raster, pos: 0 };
+        (0 .. header.height).map_while(move |_| cursor.row(&header))
+    }
+    /// The rows of a bitmap as lists of color changes, as accepted by the encoder.
+    ///
+    /// Graymaps are split at the middle gray value.
+    pub fn lines(&self) -> impl Iterator<Item=Vec<u16>> + 'a {
+        let header = self.header;
+        self.rows().map(move |row| {
+            let mut line = vec![];
+            let mut black = false;
+            for (x, &v) in row.iter().enumerate() {
+                let b = match header.kind {
+                    Kind::Bitmap => v != 0,
+                    Kind::Graymap => 2 * (v as u32) < header.maxval as u32,
+                };
+                if b != black {
+                    black = b;
+                    line.push(x as u16);
+                }
+            }
+            line
+        })
+    }
+    /// The rows as 8-bit gray values, 0 being black.
+    pub fn gray_rows(&self) -> impl Iterator<Item=Vec<u8>> + 'a {
+        let header = self.header;
+        let maxval = header.maxval as u32;
+        self.rows().map(move |row| row.iter().map(|&v| match header.kind {
+            Kind::Bitmap => if v != 0 { 0 } else { 255 },
+            Kind::Graymap => ((v as u32).min(maxval) * 255 + maxval / 2) / maxval,
+        } as u8).collect())
+    }
+    /// Read a bitmap into a `Bitmap`.
+    pub fn to_bitmap(&self) -> Option<Bitmap> {
+        match (self.header.kind, self.header.plain) {
+            (Kind::Graymap, _) => None,
+            // the raw format uses the same layout
+            (Kind::Bitmap, false) => Some(Bitmap {
+                width: self.header.width,
+                height: self.header.height,
+                data: self.raster.to_vec(),
+            }),
+            (Kind::Bitmap, true) => Some(Bitmap::from_lines(self.lines(), self.header.width)),
+        }
+    }
+}
+
+/// Write a bitmap as PBM, either raw (`P4`) or plain (`P1`).
+///
+/// Multi-image files are written by concatenating the results.
+pub fn write(bitmap: &Bitmap, plain: bool) -> Vec<u8> {
+    let magic = if plain { "P1" } else { "P4" };
+    let mut out = format!("{}\n{} {}\n", magic, bitmap.width, bitmap.height).into_bytes();
+    if !plain {
+        out.extend_from_slice(&bitmap.data);
+        return out;
+    }
+    for y in 0 .. bitmap.height {
+        // lines of plain files should not be longer than 70 characters
+        for x in 0 .. bitmap.width {
+            out.push(if bitmap.get(x, y) { b'1' } else { b'0' });
+            if x % 70 == 69 || x + 1 == bitmap.width {
+                out.push(b'\n');
+            }
+        }
+    }
+    out
+}
+
+#[test]
+fn test_netpbm() {
+    let bitmap = Bitmap::from_lines(&[vec![1, 3], vec![], vec![0, 2, 8]], 9);
+    let mut file = write(&bitmap, false);
+    file.extend(write(&bitmap, true));
+    file.extend_from_slice(b"P1 # comment\n 3\t2 # size\n 0 1 0\n101\n");
+    file.extend_from_slice(b"P2\n2 1\n# maxval\n1000 0 1000\nP5 2 1 255\n\x00\x80");
+    let images = read(&file).unwrap();
+    assert_eq!(images.len(), 5);
+    assert_eq!(images[0].to_bitmap().unwrap(), bitmap);
+    assert_eq!(images[1].to_bitmap().unwrap(), bitmap);
+    assert_eq!(images[2].lines().collect::<Vec<_>>(), [vec![1, 2], vec![0, 1, 2]]);
+    assert_eq!(images[3].gray_rows().collect::<Vec<_>>(), [[0, 255]]);
+    assert_eq!(images[4].header.maxval, 255);
+    assert_eq!(images[4].gray_rows().collect::<Vec<_>>(), [[0, 128]]);
+}
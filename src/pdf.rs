@@ -0,0 +1,583 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::ops::Range;
+use crate::{Coding, Resolution};
+use crate::tiff::{self, Image, Photometric};
+use crate::transcode::transcode_image;
+
+/// Format a length in points with at most two decimals.
+fn points(n: f64) -> String {
+    let s = format!("{:.2}", n);
+    s.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+/// The `/DecodeParms` of a CCITT coded image.
+fn decode_parms(image: &Image) -> String {
+    let k = match image.coding {
+        Coding::Group4 => -1,
+        Coding::Group3OneD => 0,
+        Coding::Group3TwoD { k } => k as i32,
+    };
+    let eol = match image.coding {
+        Coding::Group4 => "",
+        _ => " /EndOfLine true",
+    };
+    format!("<< /K {} /Columns {} /Rows {} /BlackIs1 false{} >>", k, image.width, image.height, eol)
+}
+
+/// Write a PDF with one page per image.
+///
+/// The coded data is embedded as is, as image XObject with `/Filter /CCITTFaxDecode`.
+/// Images with several strips are recoded into one first. Each page has the size of its
+/// image, taken from the resolution; images without resolution are taken to be in fine mode.
+///
+/// Fails if an image with several strips cannot be decoded.
+pub fn write(images: &[Image]) -> Option<Vec<u8>> {
+    let mut out = b"%PDF-1.4\n%\xE2\xE3\xCF\xD3\n".to_vec();
+    let mut offsets = vec![];
+    let mut object = |out: &mut Vec<u8>, dict: String, stream: Option<&[u8]>| {
+        offsets.push(out.len());
+        out.extend(format!("{} 0 obj\n{}\n", offsets.len(), dict).bytes());
+        if let Some(data) = stream {
+            out.extend_from_slice(b"stream\n");
+            out.extend_from_slice(data);
+            out.extend_from_slice(b"\nendstream\n");
+        }
+        out.extend_from_slice(b"endobj\n");
+    };
+
+    // objects 1 and 2 are the catalog and the page tree, then three objects per page
+    let kids: Vec<String> = (0 .. images.len()).map(|i| format!("{} 0 R", 3 + 3 * i)).collect();
+    object(&mut out, "<< /Type /Catalog /Pages 2 0 R >>".into(), None);
+    object(&mut out, format!("<< /Type /Pages /Kids [{}] /Count {} >>", kids.join(" "), images.len()), None);
+
+    for (i, image) in images.iter().enumerate() {
+        let single;
+        let image = match image.strips.len() {
+            1 => image,
+            _ => {
+                single = transcode_image(image, image.coding)?;
+                &single
+            }
+        };
+        let resolution = image.resolution.unwrap_or(Resolution::FINE);
+        let width = points(image.width as f64 * 72.0 / resolution.x as f64);
+        let height = points(image.height as f64 * 72.0 / resolution.y as f64);
+
+        let (page, contents, xobject) = (3 + 3 * i, 4 + 3 * i, 5 + 3 * i);
+        object(&mut out, format!(
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {w} {h}] /Resources << /XObject << /Im{p} {x} 0 R >> >> /Contents {c} 0 R >>",
+            w = width, h = height, p = page, x = xobject, c = contents
+        ), None);
+        let content = format!("q {} 0 0 {} 0 0 cm /Im{} Do Q", width, height, page);
+        object(&mut out, format!("<< /Length {} >>", content.len()), Some(content.as_bytes()));
+
+        let data = &image.strips[0];
+        // coded white shown black
+        let decode = match image.photometric {
+            Photometric::WhiteIsZero => "",
+            Photometric::BlackIsZero => " /Decode [1 0]",
+        };
+        object(&mut out, format!(
+            "<< /Type /XObject /Subtype /Image /Width {} /Height {} /ColorSpace /DeviceGray /BitsPerComponent 1{} /Filter /CCITTFaxDecode /DecodeParms {} /Length {} >>",
+            image.width, image.height, decode, decode_parms(image), data.len()
+        ), Some(data));
+    }
+
+    let xref = out.len();
+    out.extend(format!("xref\n0 {}\n0000000000 65535 f \n", offsets.len() + 1).bytes());
+    for offset in &offsets {
+        out.extend(format!("{:010} 00000 n \n", offset).bytes());
+    }
+    out.extend(format!("trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n", offsets.len() + 1, xref).bytes());
+    Some(out)
+}
+
+/// Convert all pages of a TIFF file into a PDF, without recoding the image data.
+pub fn tiff_to_pdf(data: &[u8]) -> Option<Vec<u8>> {
+    write(&tiff::read(data)?)
+}
+
+/// A PDF object, as far as needed to find images.
+#[derive(Clone, Debug, PartialEq)]
+enum Object {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Real(f64),
+    Name(Vec<u8>),
+    String(Vec<u8>),
+    Array(Vec<Object>),
+    Dict(Vec<(Vec<u8>, Object)>),
+    Ref(u32),
+    /// Dictionary and position of the data
+    Stream(Vec<(Vec<u8>, Object)>, Range<usize>),
+}
+
+fn is_space(b: u8) -> bool {
+    matches!(b, b'\0' | b'\t' | b'\n' | b'\x0C' | b'\r' | b' ')
+}
+
+fn is_delimiter(b: u8) -> bool {
+    is_space(b) || b"()<>[]{}/%".contains(&b)
+}
+
+/// Parses objects from the file, without following references.
+struct Parser<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.data.get(self.pos).cloned()
+    }
+    /// Skip whitespace and comments.
+    fn skip_space(&mut self) {
+        while let Some(b) = self.peek() {
+            if b == b'%' {
+                while self.peek().is_some_and(|b| b != b'\n' && b != b'\r') {
+                    self.pos += 1;
+                }
+            } else if is_space(b) {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+    }
+    fn token(&mut self) -> &'a [u8] {
+        let start = self.pos;
+        while self.peek().is_some_and(|b| !is_delimiter(b)) {
+            self.pos += 1;
+        }
+        &self.data[start .. self.pos]
+    }
+    fn keyword(&mut self, keyword: &[u8]) -> bool {
+        self.skip_space();
+        let start = self.pos;
+        if self.token() == keyword {
+            return true;
+        }
+        self.pos = start;
+        false
+    }
+    fn integer(&mut self) -> Option<i64> {
+        self.skip_space();
+        let start = self.pos;
+        let n = std::str::from_utf8(self.token()).ok().and_then(|s| s.parse().ok());
+        if n.is_none() {
+            self.pos = start;
+        }
+        n
+    }
+    fn dict(&mut self) -> Option<Vec<(Vec<u8>, Object)>> {
+        let mut entries = vec![];
+        loop {
+            self.skip_space();
+            if self.data.get(self.pos .. self.pos + 2)? == b">>" {
+                self.pos += 2;
+                return Some(entries);
+            }
+            match self.object()? {
+                Object::Name(key) => {
+                    let value = self.object()?;
+                    entries.push((key, value));
+                }
+                _ => return None,
+            }
+        }
+    }
+    fn object(&mut self) -> Option<Object> {
+        self.skip_space();
+        match self.peek()? {
+            b'/' => {
+                self.pos += 1;
+                Some(Object::Name(self.token().to_vec()))
+            }
+            b'<' if self.data.get(self.pos + 1) == Some(&b'<') => {
+                self.pos += 2;
+                self.dict().map(Object::Dict)
+            }
+            b'<' => {
+                self.pos += 1;
+                let start = self.pos;
+                while self.peek()? != b'>' {
+                    self.pos += 1;
+                }
+                let hex: Vec<u8> = self.data[start .. self.pos].iter().cloned().filter(|b| b.is_ascii_hexdigit()).collect();
+                self.pos += 1;
+                let digit = |b: u8| (b as char).to_digit(16).unwrap() as u8;
+                Some(Object::String(hex.chunks(2).map(|c| digit(c[0]) << 4 | c.get(1).map_or(0, |&b| digit(b))).collect()))
+            }
+            b'(' => {
+                self.pos += 1;
+                let mut depth = 1;
+                let mut string = vec![];
+                loop {
+                    let b = self.peek()?;
+                    self.pos += 1;
+                    match b {
+                        b'\\' => {
+                            let e = self.peek()?;
+                            self.pos += 1;
+                            match e {
+                                b'n' => string.push(b'\n'),
+                                b'r' => string.push(b'\r'),
+                                b't' => string.push(b'\t'),
+                                b'b' => string.push(0x08),
+                                b'f' => string.push(0x0C),
+                                b'0' ..= b'7' => {
+                                    let mut n = (e - b'0') as u32;
+                                    for _ in 0 .. 2 {
+                                        match self.data.get(self.pos) {
+                                            Some(&d @ b'0' ..= b'7') => {
+                                                n = n * 8 + (d - b'0') as u32;
+                                                self.pos += 1;
+                                            }
+                                            _ => break,
+                                        }
+                                    }
+                                    string.push(n as u8);
+                                }
+                                // line continuation
+                                b'\r' => {
+                                    if self.data.get(self.pos) == Some(&b'\n') {
+                                        self.pos += 1;
+                                    }
+                                }
+                                b'\n' => {}
+                                // `\(`, `\)`, `\\` and unknown escapes stand for the byte itself
+                                _ => string.push(e),
+                            }
+                            continue;
+                        }
+                        b'(' => depth += 1,
+                        b')' => {
+                            depth -= 1;
+                            if depth == 0 {
+                                return Some(Object::String(string));
+                            }
+                        }
+                        _ => {}
+                    }
+                    string.push(b);
+                }
+            }
+            b'[' => {
+                self.pos += 1;
+                let mut items = vec![];
+                loop {
+                    self.skip_space();
+                    if self.peek()? == b']' {
+                        self.pos += 1;
+                        return Some(Object::Array(items));
+                    }
+                    items.push(self.object()?);
+                }
+            }
+            _ => {
+                let start = self.pos;
+                let token = self.token();
+                match token {
+                    b"null" => return Some(Object::Null),
+                    b"true" => return Some(Object::Bool(true)),
+                    b"false" => return Some(Object::Bool(false)),
+                    _ => {}
+                }
+                let text = std::str::from_utf8(token).ok()?;
+                if let Ok(n) = text.parse::<i64>() {
+                    // `n g R` is a reference
+                    let after = self.pos;
+                    if self.integer().is_some() && self.keyword(b"R") {
+                        return Some(Object::Ref(u32::try_from(n).ok()?));
+                    }
+                    self.pos = after;
+                    return Some(Object::Int(n));
+                }
+                match text.parse::<f64>() {
+                    Ok(n) => Some(Object::Real(n)),
+                    Err(_) => {
+                        self.pos = start;
+                        None
+                    }
+                }
+            }
+        }
+    }
+    /// Parse `n g obj ... endobj` at the current position. Returns the object number.
+    fn indirect(&mut self) -> Option<(u32, Object)> {
+        let n = u32::try_from(self.integer()?).ok()?;
+        self.integer()?;
+        if !self.keyword(b"obj") {
+            return None;
+        }
+        let object = self.object()?;
+        let object = match object {
+            Object::Dict(dict) if self.keyword(b"stream") => {
+                // the data starts after CRLF or LF
+                if self.data.get(self.pos) == Some(&b'\r') {
+                    self.pos += 1;
+                }
+                if self.data.get(self.pos) == Some(&b'\n') {
+                    self.pos += 1;
+                }
+                let start = self.pos;
+                let end = match get(&dict, b"Length") {
+                    Some(&Object::Int(len)) if len >= 0 && self.data.get(start + len as usize ..).is_some_and(|rest| {
+                        Parser { data: rest, pos: 0 }.keyword(b"endstream")
+                    }) => start + len as usize,
+                    // the length is resolved later
+                    _ => {
+                        let end = find(&self.data[start ..], b"endstream")? + start;
+                        let data = &self.data[start .. end];
+                        end - if data.ends_with(b"\r\n") { 2 } else if data.ends_with(b"\n") || data.ends_with(b"\r") { 1 } else { 0 }
+                    }
+                };
+                self.pos = end;
+                self.keyword(b"endstream");
+                Object::Stream(dict, start .. end)
+            }
+            o => o,
+        };
+        self.keyword(b"endobj");
+        Some((n, object))
+    }
+}
+
+fn find(data: &[u8], needle: &[u8]) -> Option<usize> {
+    data.windows(needle.len()).position(|w| w == needle)
+}
+
+fn get<'o>(dict: &'o [(Vec<u8>, Object)], key: &[u8]) -> Option<&'o Object> {
+    dict.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+}
+
+/// All objects of a PDF file, by number.
+struct Objects<'a> {
+    data: &'a [u8],
+    objects: HashMap<u32, Object>,
+}
+impl<'a> Objects<'a> {
+    /// Scan the file for indirect objects, including those in uncompressed object streams.
+    ///
+    /// Later definitions replace earlier ones, as with incremental updates.
+    fn scan(data: &'a [u8]) -> Self {
+        let mut objects = HashMap::new();
+        let mut parser = Parser { data, pos: 0 };
+        while parser.pos < data.len() {
+            let start = parser.pos;
+            match parser.indirect() {
+                Some((n, object)) => {
+                    objects.insert(n, object);
+                }
+                // skip to the next line
+                None => {
+                    parser.pos = start;
+                    while parser.peek().is_some_and(|b| b != b'\n' && b != b'\r') {
+                        parser.pos += 1;
+                    }
+                    parser.pos += 1;
+                }
+            }
+        }
+        let mut objects = Objects { data, objects };
+        objects.fix_lengths();
+        objects.read_object_streams();
+        objects
+    }
+    fn resolve<'o>(&'o self, object: &'o Object) -> &'o Object {
+        match object {
+            Object::Ref(n) => self.objects.get(n).unwrap_or(&Object::Null),
+            o => o,
+        }
+    }
+    /// Apply stream lengths given as reference.
+    fn fix_lengths(&mut self) {
+        let lengths: Vec<(u32, usize)> = self.objects.iter().filter_map(|(&n, object)| match object {
+            Object::Stream(dict, range) => match self.resolve(get(dict, b"Length")?) {
+                &Object::Int(len) if len >= 0 && range.start + len as usize <= self.data.len() => Some((n, len as usize)),
+                _ => None,
+            },
+            _ => None,
+        }).collect();
+        for (n, len) in lengths {
+            if let Some(Object::Stream(_, range)) = self.objects.get_mut(&n) {
+                range.end = range.start + len;
+            }
+        }
+    }
+    /// Read the objects of object streams without filter.
+    fn read_object_streams(&mut self) {
+        let mut found = vec![];
+        for object in self.objects.values() {
+            let (dict, range) = match object {
+                Object::Stream(dict, range) if get(dict, b"Type") == Some(&Object::Name(b"ObjStm".to_vec())) => (dict, range),
+                _ => continue,
+            };
+            if get(dict, b"Filter").is_some() {
+                continue;
+            }
+            let (count, first) = match (get(dict, b"N"), get(dict, b"First")) {
+                (Some(&Object::Int(count)), Some(&Object::Int(first))) => (count, first as usize),
+                _ => continue,
+            };
+            let data = &self.data[range.clone()];
+            let mut header = Parser { data, pos: 0 };
+            for _ in 0 .. count {
+                let (n, offset) = match (header.integer(), header.integer()) {
+                    (Some(n), Some(offset)) => (n as u32, offset as usize),
+                    _ => break,
+                };
+                if let Some(object) = (Parser { data, pos: first + offset }).object() {
+                    found.push((n, object));
+                }
+            }
+        }
+        for (n, object) in found {
+            self.objects.entry(n).or_insert(object);
+        }
+    }
+}
+
+/// Find all images coded with `/Filter /CCITTFaxDecode` in a PDF file.
+///
+/// Only unencrypted files are supported, and objects in object streams only if the
+/// streams are not compressed. Images with other filters, with Group 3 data without EOL
+/// codes or with byte aligned lines are skipped. The images are returned in the order of
+/// their object numbers, with the photometric interpretation matching `/BlackIs1` and
+/// `/Decode`, and without resolution.
+pub fn extract_images(data: &[u8]) -> Vec<Image> {
+    let objects = Objects::scan(data);
+    let mut numbers: Vec<u32> = objects.objects.keys().cloned().collect();
+    numbers.sort_unstable();
+    numbers.iter().filter_map(|n| match &objects.objects[n] {
+        Object::Stream(dict, range) => ccitt_image(&objects, dict, &data[range.clone()]),
+        _ => None,
+    }).collect()
+}
+
+fn ccitt_image(objects: &Objects, dict: &[(Vec<u8>, Object)], data: &[u8]) -> Option<Image> {
+    let name = |o: &Object, n: &[u8]| matches!(objects.resolve(o), Object::Name(name) if name == n);
+    let int = |o: Option<&Object>| match o.map(|o| objects.resolve(o)) {
+        Some(&Object::Int(n)) => Some(n),
+        _ => None,
+    };
+    if !get(dict, b"Subtype").is_some_and(|o| name(o, b"Image")) {
+        return None;
+    }
+    // a single filter, possibly in an array
+    let parms = match objects.resolve(get(dict, b"Filter")?) {
+        f @ Object::Name(_) if name(f, b"CCITTFaxDecode") => get(dict, b"DecodeParms"),
+        Object::Array(filters) if filters.len() == 1 && name(&filters[0], b"CCITTFaxDecode") => {
+            match get(dict, b"DecodeParms").map(|o| objects.resolve(o)) {
+                Some(Object::Array(parms)) => parms.first(),
+                parms => parms,
+            }
+        }
+        _ => return None,
+    };
+    let empty = vec![];
+    let parms = match parms.map(|o| objects.resolve(o)) {
+        Some(Object::Dict(parms)) => parms,
+        _ => &empty,
+    };
+    let flag = |key: &[u8]| matches!(get(parms, key).map(|o| objects.resolve(o)), Some(Object::Bool(true)));
+
+    let width = int(get(dict, b"Width"))?;
+    let height = int(get(dict, b"Height"))?;
+    let columns = int(get(parms, b"Columns")).unwrap_or(1728);
+    if columns != width || flag(b"EncodedByteAlign") {
+        return None;
+    }
+    let k = int(get(parms, b"K")).unwrap_or(0);
+    let coding = match k {
+        k if k < 0 => Coding::Group4,
+        _ if !flag(b"EndOfLine") => return None,
+        0 => Coding::Group3OneD,
+        k => Coding::Group3TwoD { k: k.min(u16::MAX as i64) as u16 },
+    };
+    // `/Decode [1 0]` swaps black and white once more
+    let inverted = match get(dict, b"Decode").map(|o| objects.resolve(o)) {
+        Some(Object::Array(decode)) => match decode.first().map(|o| objects.resolve(o)) {
+            Some(&Object::Int(n)) => n == 1,
+            Some(&Object::Real(n)) => n == 1.0,
+            _ => false,
+        },
+        _ => false,
+    };
+    let photometric = if flag(b"BlackIs1") != inverted { Photometric::BlackIsZero } else { Photometric::WhiteIsZero };
+    let height = int(get(parms, b"Rows")).filter(|&rows| rows > 0).unwrap_or(height);
+    Some(Image {
+        width: u32::try_from(width).ok()?,
+        height: u32::try_from(height).ok()?,
+        coding,
+        photometric,
+        resolution: None,
+        rows_per_strip: u32::try_from(height).ok()?,
+        strips: vec![data.to_vec()],
+    })
+}
+
+/// Extract all CCITT coded images of a PDF file into a TIFF file, without recoding them.
+///
+/// Returns `None` if there are none.
+pub fn pdf_to_tiff(data: &[u8], options: tiff::Options) -> Option<Vec<u8>> {
+    let images = extract_images(data);
+    if images.is_empty() {
+        return None;
+    }
+    Some(tiff::write(&images, options))
+}
+
+#[test]
+fn test_pdf_write() {
+    let image = Image {
+        width: 1728,
+        height: 2,
+        coding: Coding::Group4,
+        photometric: Photometric::WhiteIsZero,
+        resolution: Some(Resolution::STANDARD),
+        rows_per_strip: 2,
+        strips: vec![vec![0x26, 0xA0, 0x00, 0x10, 0x01]],
+    };
+    let pdf = write(&[image.clone(), image]).unwrap();
+    let text = String::from_utf8_lossy(&pdf);
+    assert!(text.contains("/Count 2"));
+    assert!(text.contains("/MediaBox [0 0 609.88 1.47]"));
+    assert!(text.contains("/DecodeParms << /K -1 /Columns 1728 /Rows 2 /BlackIs1 false >> /Length 5"));
+
+    // every xref entry points at its object
+    let xref = text.rfind("xref\n").unwrap();
+    for (i, entry) in text[xref ..].lines().skip(3).take(8).enumerate() {
+        let offset: usize = entry[.. 10].parse().unwrap();
+        assert!(pdf[offset ..].starts_with(format!("{} 0 obj", i + 1).as_bytes()));
+    }
+}
+
+#[test]
+fn test_pdf_extract() {
+    let image = Image {
+        width: 16,
+        height: 3,
+        coding: Coding::Group4,
+        photometric: Photometric::BlackIsZero,
+        resolution: None,
+        rows_per_strip: 3,
+        strips: vec![b"coded)\nendstream".to_vec()],
+    };
+    let pdf = write(std::slice::from_ref(&image)).unwrap();
+    assert_eq!(extract_images(&pdf), std::slice::from_ref(&image));
+
+    // indirect length and parameters in an uncompressed object stream
+    let objects = b"<< /K 0 /Columns 16 /BlackIs1 true /EndOfLine true >>";
+    let mut pdf = b"%PDF-1.5\n".to_vec();
+    pdf.extend(format!("1 0 obj << /Type /ObjStm /N 1 /First 4 /Length {} >> stream\n7 0 ", objects.len() + 4).bytes());
+    pdf.extend_from_slice(objects);
+    pdf.extend_from_slice(b"\nendstream endobj\n");
+    pdf.extend_from_slice(b"2 0 obj << /Subtype /Image /Width 16 /Height 3 /Filter [/CCITTFaxDecode] /DecodeParms [7 0 R] /Length 3 0 R >>\nstream\r\nabcd\r\nendstream\nendobj\n3 0 obj 4 endobj\n");
+    let images = extract_images(&pdf);
+    assert_eq!(images, [Image { coding: Coding::Group3OneD, strips: vec![b"abcd".to_vec()], ..image }]);
+
+    let string = Parser { data: b"(a\\)b\\\\c\\101\\0a\\\r\nd(e)\\n)", pos: 0 }.object();
+    assert_eq!(string, Some(Object::String(b"a)b\\cA\0ad(e)\n".to_vec())));
+}
@@ -0,0 +1,344 @@
+use crate::bitmap::Bitmap;
+use crate::tiff::Photometric;
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 { 0xEDB8_8320 ^ (c >> 1) } else { c >> 1 };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+}
+const CRC_TABLE: [u32; 256] = crc_table();
+
+/// CRC-32 as used by PNG (and zip, gzip, ...).
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |c, &b| CRC_TABLE[((c ^ b as u32) & 0xFF) as usize] ^ (c >> 8))
+}
+
+/// Adler-32 checksum of zlib streams.
+pub fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    // 5552 bytes can be summed up before `b` may overflow
+    for chunk in data.chunks(5552) {
+        for &x in chunk {
+            a += x as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    b << 16 | a
+}
+
+/// Collects bits least significant first, as deflate wants them.
+struct LsbWriter {
+    data: Vec<u8>,
+    acc: u32,
+    len: u8,
+}
+impl LsbWriter {
+    fn write(&mut self, bits: u32, len: u8) {
+        self.acc |= bits << self.len;
+        self.len += len;
+        while self.len >= 8 {
+            self.data.push(self.acc as u8);
+            self.acc >>= 8;
+            self.len -= 8;
+        }
+    }
+    /// Write a Huffman code, which is packed starting with its most significant bit.
+    fn write_code(&mut self, code: u32, len: u8) {
+        self.write(code.reverse_bits() >> (32 - len), len);
+    }
+    fn finish(mut self) -> Vec<u8> {
+        if self.len > 0 {
+            self.data.push(self.acc as u8);
+        }
+        self.data
+    }
+}
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258
+];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769,
+    1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577
+];
+const DIST_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+
+/// Write a literal/length symbol with the fixed Huffman code.
+fn write_symbol(out: &mut LsbWriter, symbol: u16) {
+    let s = symbol as u32;
+    match symbol {
+        0 ..= 143 => out.write_code(0x30 + s, 8),
+        144 ..= 255 => out.write_code(0x190 + s - 144, 9),
+        256 ..= 279 => out.write_code(s - 256, 7),
+        _ => out.write_code(0xC0 + s - 280, 8),
+    }
+}
+
+fn write_match(out: &mut LsbWriter, len: u16, dist: u16) {
+    let i = LENGTH_BASE.iter().rposition(|&b| b <= len).unwrap();
+    write_symbol(out, 257 + i as u16);
+    out.write((len - LENGTH_BASE[i]) as u32, LENGTH_EXTRA[i]);
+    let i = DIST_BASE.iter().rposition(|&b| b <= dist).unwrap();
+    out.write_code(i as u32, 5);
+    out.write((dist - DIST_BASE[i]) as u32, DIST_EXTRA[i]);
+}
+
+/// Compress `data` into a single deflate block with the fixed Huffman codes,
+/// finding matches through hash chains.
+pub fn deflate(data: &[u8]) -> Vec<u8> {
+    const WINDOW: usize = 32768;
+    const MAX_CHAIN: usize = 64;
+    const HASH_BITS: u32 = 15;
+    let hash = |i: usize| {
+        let v = (data[i] as u32) << 16 | (data[i + 1] as u32) << 8 | data[i + 2] as u32;
+        (v.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+    };
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut prev = vec![usize::MAX; data.len()];
+    let insert = |head: &mut [usize], prev: &mut [usize], i: usize| {
+        if i + 3 <= data.len() {
+            let h = hash(i);
+            prev[i] = head[h];
+            head[h] = i;
+        }
+    };
+
+    let mut out = LsbWriter { data: vec![], acc: 0, len: 0 };
+    // final block, fixed Huffman codes
+    out.write(0b011, 3);
+    let mut i = 0;
+    while i < data.len() {
+        let mut best = (0, 0);
+        if i + 3 <= data.len() {
+            let max_len = (data.len() - i).min(258);
+            let mut candidate = head[hash(i)];
+            let mut chain = 0;
+            while candidate != usize::MAX && i - candidate <= WINDOW && chain < MAX_CHAIN {
+                let len = data[candidate ..].iter().zip(&data[i .. i + max_len]).take_while(|(a, b)| a == b).count();
+                if len > best.0 {
+                    best = (len, i - candidate);
+                    if len == max_len {
+                        break;
+                    }
+                }
+                candidate = prev[candidate];
+                chain += 1;
+            }
+        }
+        if best.0 >= 3 {
+            write_match(&mut out, best.0 as u16, best.1 as u16);
+            for j in i .. i + best.0 {
+                insert(&mut head, &mut prev, j);
+            }
+            i += best.0;
+        } else {
+            write_symbol(&mut out, data[i] as u16);
+            insert(&mut head, &mut prev, i);
+            i += 1;
+        }
+    }
+    write_symbol(&mut out, 256);
+    out.finish()
+}
+
+/// Wrap `data` in a zlib stream.
+pub fn zlib(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    out.extend(deflate(data));
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = out.len();
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let crc = crc32(&out[start ..]);
+    out.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// Writes decoded rows as a 1-bit grayscale PNG.
+///
+/// Each row is filtered with either no filter or the Up filter, whichever leaves more zero
+/// bytes. For bi-level pages this turns lines equal to the previous one into zeros.
+pub struct PngEncoder {
+    bitmap: Bitmap,
+    photometric: Photometric,
+}
+impl PngEncoder {
+    /// With `Photometric::WhiteIsZero` the coded white is shown white, with
+    /// `Photometric::BlackIsZero` it is shown black.
+    pub fn new(width: u16, photometric: Photometric) -> Self {
+        PngEncoder { bitmap: Bitmap::empty(width), photometric }
+    }
+    /// Add a line given as list of color changes (as produced by the decoder).
+    pub fn push_line(&mut self, line: &[u16]) {
+        self.bitmap.push_line(line);
+    }
+    pub fn finish(self) -> Vec<u8> {
+        encode(&self.bitmap, self.photometric)
+    }
+}
+
+/// Encode a bitmap as 1-bit grayscale PNG. See `PngEncoder` for `photometric`.
+pub fn encode(bitmap: &Bitmap, photometric: Photometric) -> Vec<u8> {
+    let stride = bitmap.stride();
+    // PNG uses 0 for black, the bitmap 1
+    let invert = match photometric {
+        Photometric::WhiteIsZero => 0xFF,
+        Photometric::BlackIsZero => 0,
+    };
+    let mut raw = Vec::with_capacity((stride + 1) * bitmap.height as usize);
+    let mut previous = vec![0; stride];
+    let mut row = vec![0; stride];
+    for y in 0 .. bitmap.height {
+        for (r, &b) in row.iter_mut().zip(bitmap.row(y)) {
+            *r = b ^ invert;
+        }
+        let zeros = row.iter().filter(|&&b| b == 0).count();
+        let zeros_up = row.iter().zip(&previous).filter(|(a, b)| a == b).count();
+        if zeros_up > zeros {
+            raw.push(2);
+            raw.extend(row.iter().zip(&previous).map(|(a, b)| a.wrapping_sub(*b)));
+        } else {
+            raw.push(0);
+            raw.extend_from_slice(&row);
+        }
+        std::mem::swap(&mut previous, &mut row);
+    }
+
+    let mut out = b"\x89PNG\r\n\x1a\n".to_vec();
+    let mut header = vec![];
+    header.extend_from_slice(&(bitmap.width as u32).to_be_bytes());
+    header.extend_from_slice(&(bitmap.height as u32).to_be_bytes());
+    // bit depth 1, grayscale, deflate, adaptive filtering, no interlace
+    header.extend_from_slice(&[1, 0, 0, 0, 0]);
+    chunk(&mut out, b"IHDR", &header);
+    chunk(&mut out, b"IDAT", &zlib(&raw));
+    chunk(&mut out, b"IEND", &[]);
+    out
+}
+
+#[test]
+fn test_png() {
+    use std::convert::TryInto;
+    assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    // three literals and a match of length 6 at distance 3
+    assert_eq!(deflate(b"abcabcabc"), [0x4B, 0x4C, 0x4A, 0x86, 0x20, 0x00]);
+
+    let mut encoder = PngEncoder::new(10, Photometric::WhiteIsZero);
+    for _ in 0 .. 50 {
+        encoder.push_line(&[2, 5]);
+    }
+    let png = encoder.finish();
+    assert_eq!(&png[12 .. 16], b"IHDR");
+    assert_eq!(&png[16 .. 29], &[0, 0, 0, 10, 0, 0, 0, 50, 1, 0, 0, 0, 0]);
+    assert_eq!(u32::from_be_bytes(png[29 .. 33].try_into().unwrap()), crc32(&png[12 .. 29]));
+    assert_eq!(&png[png.len() - 12 ..], b"\0\0\0\0IEND\xAE\x42\x60\x82");
+
+    // a minimal inflater for stored and fixed Huffman blocks
+    fn bits(data: &[u8], pos: &mut usize, n: u8) -> usize {
+        let mut v = 0;
+        for i in 0 .. n {
+            v |= ((data[*pos / 8] >> (*pos % 8)) as usize & 1) << i;
+            *pos += 1;
+        }
+        v
+    }
+    fn code(data: &[u8], pos: &mut usize, n: u8) -> usize {
+        (0 .. n).fold(0, |c, _| c << 1 | bits(data, pos, 1))
+    }
+    fn inflate(data: &[u8]) -> Vec<u8> {
+        let mut pos = 0;
+        let mut out: Vec<u8> = vec![];
+        loop {
+            let last = bits(data, &mut pos, 1) == 1;
+            match bits(data, &mut pos, 2) {
+                0 => {
+                    let start = pos.div_ceil(8);
+                    let len = u16::from_le_bytes([data[start], data[start + 1]]) as usize;
+                    out.extend_from_slice(&data[start + 4 .. start + 4 + len]);
+                    pos = (start + 4 + len) * 8;
+                }
+                1 => loop {
+                    let c = code(data, &mut pos, 7);
+                    let symbol = match c {
+                        0 ..= 23 => c + 256,
+                        _ => match c << 1 | bits(data, &mut pos, 1) {
+                            c @ 0x30 ..= 0xBF => c - 0x30,
+                            c @ 0xC0 ..= 0xC7 => c - 0xC0 + 280,
+                            c => (c << 1 | bits(data, &mut pos, 1)) - 0x190 + 144,
+                        },
+                    };
+                    match symbol {
+                        0 ..= 255 => out.push(symbol as u8),
+                        256 => break,
+                        _ => {
+                            let i = symbol - 257;
+                            let len = LENGTH_BASE[i] as usize + bits(data, &mut pos, LENGTH_EXTRA[i]);
+                            let i = code(data, &mut pos, 5);
+                            let dist = DIST_BASE[i] as usize + bits(data, &mut pos, DIST_EXTRA[i]);
+                            for _ in 0 .. len {
+                                out.push(out[out.len() - dist]);
+                            }
+                        }
+                    }
+                },
+                _ => panic!("dynamic Huffman codes are not used"),
+            }
+            if last {
+                return out;
+            }
+        }
+    }
+    assert_eq!(inflate(&[0x01, 0x03, 0x00, 0xFC, 0xFF, b'a', b'b', b'c']), b"abc");
+    let data: Vec<u8> = (0 .. 100_000u32).map(|i| if i % 7000 < 3000 { (i / 40) as u8 } else { (i.wrapping_mul(i) >> 9) as u8 }).collect();
+    assert_eq!(inflate(&deflate(&data)), data);
+
+    // the rows come back from IDAT, unfiltered and inverted
+    let lines: Vec<Vec<u16>> = (0 .. 120).map(|y| match y % 30 {
+        0 ..= 9 => vec![],
+        10 ..= 19 => vec![0, 300],
+        _ => vec![y, y + 3, 150 - y / 2, 299],
+    }).collect();
+    let bitmap = Bitmap::from_lines(&lines, 301);
+    let png = encode(&bitmap, Photometric::WhiteIsZero);
+    let mut pos = 8;
+    let mut idat = vec![];
+    while pos < png.len() {
+        let len = u32::from_be_bytes(png[pos .. pos + 4].try_into().unwrap()) as usize;
+        if &png[pos + 4 .. pos + 8] == b"IDAT" {
+            idat.extend_from_slice(&png[pos + 8 .. pos + 8 + len]);
+        }
+        pos += len + 12;
+    }
+    let raw = inflate(&idat[2 .. idat.len() - 4]);
+    assert_eq!(idat[idat.len() - 4 ..], adler32(&raw).to_be_bytes());
+    let stride = bitmap.stride();
+    let mut previous = vec![0; stride];
+    for (y, row) in raw.chunks(stride + 1).enumerate() {
+        let unfiltered: Vec<u8> = match row[0] {
+            0 => row[1 ..].to_vec(),
+            2 => row[1 ..].iter().zip(&previous).map(|(a, b)| a.wrapping_add(*b)).collect(),
+            filter => panic!("filter {}", filter),
+        };
+        assert_eq!(unfiltered.iter().map(|b| b ^ 0xFF).collect::<Vec<_>>(), bitmap.row(y as u16));
+        previous = unfiltered;
+    }
+    assert_eq!(raw.len(), (stride + 1) * 120);
+}
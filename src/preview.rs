@@ -0,0 +1,111 @@
+use std::convert::TryFrom;
+use crate::Coding;
+use crate::runs;
+use crate::tiff::Image;
+use crate::transcode::decode_any;
+
+/// An 8-bit grayscale image, one byte per pixel, 0 is black and 255 is white.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GrayImage {
+    pub width: u16,
+    pub height: u16,
+    pub data: Vec<u8>,
+}
+
+/// Reduces a page, fed line by line as lists of color changes, by an integer factor
+/// with a box filter.
+///
+/// Every output pixel is the share of white in a `factor` x `factor` block of pels.
+/// The black area is summed up from the runs, the pels are never expanded.
+pub struct Downsampler {
+    width: u16,
+    factor: u16,
+    /// black pels per output pixel of the current row
+    black: Vec<u32>,
+    /// input lines in the current row
+    lines: u16,
+    image: GrayImage,
+}
+impl Downsampler {
+    pub fn new(width: u16, factor: u16) -> Self {
+        assert!(factor > 0);
+        let new_width = width.div_ceil(factor);
+        Downsampler {
+            width,
+            factor,
+            black: vec![0; new_width as usize],
+            lines: 0,
+            image: GrayImage { width: new_width, height: 0, data: vec![] },
+        }
+    }
+    /// Add the next input line.
+    pub fn push(&mut self, line: &[u16]) {
+        let f = self.factor as u32;
+        for (start, end) in runs::spans(line, self.width) {
+            let (start, end) = (start as u32, end as u32);
+            let (first, last) = (start / f, (end - 1) / f);
+            if first == last {
+                self.black[first as usize] += end - start;
+                continue;
+            }
+            self.black[first as usize] += (first + 1) * f - start;
+            for b in &mut self.black[first as usize + 1 .. last as usize] {
+                *b += f;
+            }
+            self.black[last as usize] += end - last * f;
+        }
+        self.lines += 1;
+        if self.lines == self.factor {
+            self.flush();
+        }
+    }
+    fn flush(&mut self) {
+        let f = self.factor as u32;
+        let width = self.width as u32;
+        for (i, b) in self.black.iter_mut().enumerate() {
+            // the last column may be narrower
+            let cols = (width - i as u32 * f).min(f);
+            let area = cols * self.lines as u32;
+            let white = area - *b;
+            self.image.data.push(((white * 255 + area / 2) / area) as u8);
+            *b = 0;
+        }
+        self.image.height += 1;
+        self.lines = 0;
+    }
+    /// Return the image, including a last row of fewer than `factor` lines.
+    pub fn finish(mut self) -> GrayImage {
+        if self.lines > 0 {
+            self.flush();
+        }
+        self.image
+    }
+}
+
+/// Decode a raw coded stream and reduce it by `factor`.
+pub fn preview(input: &[u8], width: u16, coding: Coding, factor: u16) -> Option<GrayImage> {
+    let mut downsampler = Downsampler::new(width, factor);
+    decode_any(input, width, coding, |line| downsampler.push(line))?;
+    Some(downsampler.finish())
+}
+
+/// Decode a TIFF image and reduce it by `factor`. Photometric interpretation is not applied.
+pub fn preview_image(image: &Image, factor: u16) -> Option<GrayImage> {
+    let width = u16::try_from(image.width).ok()?;
+    let mut downsampler = Downsampler::new(width, factor);
+    image.decode(|line| downsampler.push(line))?;
+    Some(downsampler.finish())
+}
+
+#[test]
+fn test_preview() {
+    let mut d = Downsampler::new(7, 3);
+    d.push(&[0, 3, 4, 5]);
+    d.push(&[]);
+    d.push(&[1, 7]);
+    d.push(&[6]);
+    let image = d.finish();
+    assert_eq!((image.width, image.height), (3, 2));
+    // black pels: 5 of 9, 4 of 9, 1 of 3 / 0 of 3, 0 of 3, 1 of 1
+    assert_eq!(image.data, [113, 142, 170, 255, 255, 0]);
+}
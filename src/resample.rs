@@ -0,0 +1,173 @@
+use std::convert::TryFrom;
+use crate::{Coding, Resolution};
+use crate::runs;
+use crate::tiff::Image;
+use crate::transcode::{AnyEncoder, decode_any};
+
+/// Converts a page, fed line by line as lists of color changes, to a different resolution.
+///
+/// Lines are repeated to increase the vertical resolution. To reduce it, lines are dropped
+/// and merged into the line that is kept with OR, so thin horizontal strokes survive.
+/// Horizontally a run covers every pel of the new line it overlaps, which doubles the
+/// runs when going from 204 to 408 dpi and OR-merges pairs of pels when halving.
+pub struct Resampler<F> {
+    from: Resolution,
+    to: Resolution,
+    width: u16,
+    new_width: u16,
+    /// number of input lines so far
+    lines: u32,
+    /// OR of the scaled input lines not yet written
+    pending: Option<Vec<u16>>,
+    line_cb: F,
+}
+impl<F: FnMut(&[u16])> Resampler<F> {
+    pub fn new(from: Resolution, to: Resolution, width: u16, line_cb: F) -> Self {
+        assert!(from.x > 0 && from.y > 0 && to.x > 0 && to.y > 0);
+        Resampler {
+            from,
+            to,
+            width,
+            new_width: scaled_width(width, from.x, to.x),
+            lines: 0,
+            pending: None,
+            line_cb,
+        }
+    }
+    /// Width of the output lines.
+    pub fn new_width(&self) -> u16 {
+        self.new_width
+    }
+    /// Add the next input line.
+    pub fn push(&mut self, line: &[u16]) {
+        let (from, to) = (self.from.x as u32, self.to.x as u32);
+        let spans = runs::spans(line, self.width)
+            .map(|(a, b)| (a as u32 * to / from, (b as u32 * to).div_ceil(from)))
+            .map(|(a, b)| (a.min(u16::MAX as u32) as u16, b.min(u16::MAX as u32) as u16));
+        let scaled = runs::from_spans(spans, self.new_width);
+        self.pending = Some(match self.pending.take() {
+            Some(pending) => runs::or(&pending, &scaled, self.new_width),
+            None => scaled,
+        });
+
+        // the input line `i` covers the output lines `out(i) .. out(i + 1)`
+        let (num, den) = line_ratio(self.from.y, self.to.y);
+        let out = |i: u32| (i as u64 * num as u64 / den as u64) as u32;
+        let count = out(self.lines + 1) - out(self.lines);
+        self.lines += 1;
+        if count > 0 {
+            let pending = self.pending.take().unwrap();
+            for _ in 0 .. count {
+                (self.line_cb)(&pending);
+            }
+        }
+    }
+    /// Write the remaining merged lines, if any.
+    pub fn finish(mut self) {
+        if let Some(pending) = self.pending.take() {
+            (self.line_cb)(&pending);
+        }
+    }
+}
+
+/// Ratio of output to input lines. The nominal line densities (98, 196, 391) are
+/// rounded, so ratios within 1% of an integer are made exact.
+fn line_ratio(from: u16, to: u16) -> (u32, u32) {
+    let (from, to) = (from as u32, to as u32);
+    let near = |small: u32, large: u32| {
+        let n = (large + small / 2) / small;
+        n > 0 && (n * small).abs_diff(large) * 100 <= large
+    };
+    if to >= from && near(from, to) {
+        ((to + from / 2) / from, 1)
+    } else if from > to && near(to, from) {
+        (1, (from + to / 2) / to)
+    } else {
+        (to, from)
+    }
+}
+
+fn scaled_width(width: u16, from: u16, to: u16) -> u16 {
+    let w = (width as u32 * to as u32 + from as u32 / 2) / from as u32;
+    w.min(u16::MAX as u32) as u16
+}
+
+/// Convert a raw coded stream from resolution `from` to `to`, keeping the coding.
+///
+/// Returns the new data and the new width.
+pub fn resample(input: &[u8], width: u16, coding: Coding, from: Resolution, to: Resolution) -> Option<(Vec<u8>, u16)> {
+    let mut encoder = AnyEncoder::new(coding);
+    let new_width = scaled_width(width, from.x, to.x);
+    let mut resampler = Resampler::new(from, to, width, |line: &[u16]| encoder.encode_transitions(line, new_width));
+    decode_any(input, width, coding, |line| resampler.push(line))?;
+    resampler.finish();
+    Some((encoder.finish(), new_width))
+}
+
+/// Convert a TIFF image to the resolution `to`. The result has a single strip.
+///
+/// Fails if the image does not specify its resolution.
+pub fn resample_image(image: &Image, to: Resolution) -> Option<Image> {
+    let from = image.resolution?;
+    let width = u16::try_from(image.width).ok()?;
+    let new_width = scaled_width(width, from.x, to.x);
+    let mut encoder = AnyEncoder::new(image.coding);
+    let mut height = 0;
+    let mut resampler = Resampler::new(from, to, width, |line: &[u16]| {
+        encoder.encode_transitions(line, new_width);
+        height += 1;
+    });
+    image.decode(|line| resampler.push(line))?;
+    resampler.finish();
+    Some(Image {
+        width: new_width as u32,
+        height,
+        resolution: Some(to),
+        rows_per_strip: height,
+        strips: vec![encoder.finish()],
+        ..image.clone()
+    })
+}
+
+#[test]
+fn test_resample() {
+    let width = 16;
+    let lines: Vec<Vec<u16>> = vec![vec![1, 2], vec![], vec![4, 8], vec![15]];
+    let run = |from, to| {
+        let mut out = vec![];
+        let mut resampler = Resampler::new(from, to, width, |line: &[u16]| out.push(line.to_vec()));
+        let new_width = resampler.new_width();
+        for line in &lines {
+            resampler.push(line);
+        }
+        resampler.finish();
+        (out, new_width)
+    };
+    assert_eq!(run(Resolution::STANDARD, Resolution::FINE).0, [
+        vec![1, 2], vec![1, 2], vec![], vec![], vec![4, 8], vec![4, 8], vec![15], vec![15]
+    ]);
+    assert_eq!(run(Resolution::FINE, Resolution::STANDARD).0, [vec![1, 2], vec![4, 8, 15]]);
+    assert_eq!(run(Resolution::SUPERFINE, Resolution::ULTRAFINE), (
+        vec![vec![2, 4], vec![], vec![8, 16], vec![30]], 32
+    ));
+    assert_eq!(run(Resolution::ULTRAFINE, Resolution::SUPERFINE), (
+        vec![vec![0, 1], vec![], vec![2, 4], vec![7]], 8
+    ));
+
+    let mut out = 0;
+    let mut resampler = Resampler::new(Resolution::STANDARD, Resolution::SUPERFINE, width, |_: &[u16]| out += 1);
+    for line in crate::test_lines(100, width) {
+        resampler.push(&line);
+    }
+    resampler.finish();
+    assert_eq!(out, 400);
+
+    let mut encoder = AnyEncoder::new(Coding::Group4);
+    for line in &lines {
+        encoder.encode_transitions(line, width);
+    }
+    let (data, new_width) = resample(&encoder.finish(), width, Coding::Group4, Resolution::FINE, Resolution::STANDARD).unwrap();
+    let mut decoded = vec![];
+    crate::decoder::decode_g4(data.iter().cloned(), new_width, None, |line| decoded.push(line.to_vec())).unwrap();
+    assert_eq!(decoded, [vec![1, 2], vec![4, 8, 15]]);
+}
@@ -0,0 +1,181 @@
+/// Write the normalized form of `line` into `out`.
+pub(crate) fn normalize_into(out: &mut Vec<u16>, line: &[u16], width: u16) {
+    out.clear();
+    out.extend(line.iter().cloned().filter(|&t| t < width));
+    if !out.is_sorted() {
+        out.sort_unstable();
+    }
+    // every position toggles the color, so pairs at the same position cancel
+    let mut len = 0;
+    for i in 0 .. out.len() {
+        if len > 0 && out[len - 1] == out[i] {
+            len -= 1;
+        } else {
+            out[len] = out[i];
+            len += 1;
+        }
+    }
+    out.truncate(len);
+}
+
+/// Drop positions outside the line and pairs of changes at the same position.
+///
+/// Each position toggles the color, positions out of order are sorted in.
+pub fn normalize(line: &[u16], width: u16) -> Vec<u16> {
+    let mut out = Vec::with_capacity(line.len());
+    normalize_into(&mut out, line, width);
+    out
+}
+
+/// Iterate over the black runs of a line as `(start, end)` pairs, `end` being exclusive.
+pub fn spans(line: &[u16], width: u16) -> impl Iterator<Item=(u16, u16)> + '_ {
+    let mut iter = line.iter().cloned().take_while(move |&t| t < width);
+    std::iter::from_fn(move || {
+        let start = iter.next()?;
+        Some((start, iter.next().unwrap_or(width)))
+    }).filter(|&(start, end)| start < end)
+}
+
+/// Build a line from black runs, which have to be sorted by their start.
+///
+/// Overlapping and adjacent runs are merged, runs are clipped to `width`.
+pub fn from_spans(spans: impl IntoIterator<Item=(u16, u16)>, width: u16) -> Vec<u16> {
+    let mut out: Vec<u16> = vec![];
+    for (start, end) in spans {
+        let end = end.min(width);
+        if start >= end {
+            continue;
+        }
+        match out.last_mut() {
+            Some(last) if *last >= start => *last = (*last).max(end),
+            _ => {
+                out.push(start);
+                out.push(end);
+            }
+        }
+    }
+    if out.last() == Some(&width) {
+        out.pop();
+    }
+    out
+}
+
+/// Whether the pel at `x` is black.
+pub fn is_black(line: &[u16], width: u16, x: u16) -> bool {
+    spans(line, width).any(|(start, end)| start <= x && x < end)
+}
+
+/// Cut out the columns `start .. end`. The result has a width of `end - start`.
+pub fn crop(line: &[u16], width: u16, start: u16, end: u16) -> Vec<u16> {
+    let end = end.min(width);
+    let spans = spans(line, width)
+        .map(|(a, b)| (a.max(start), b.min(end)))
+        .filter(|&(a, b)| a < b)
+        .map(|(a, b)| (a - start, b - start));
+    from_spans(spans, end.saturating_sub(start))
+}
+
+/// Swap black and white.
+pub fn invert(line: &[u16], width: u16) -> Vec<u16> {
+    let line = normalize(line, width);
+    match line.first() {
+        Some(0) => line[1..].to_vec(),
+        _ if width == 0 => line,
+        _ => std::iter::once(0).chain(line).collect(),
+    }
+}
+
+/// Mirror the line horizontally.
+pub fn mirror(line: &[u16], width: u16) -> Vec<u16> {
+    let mut spans: Vec<(u16, u16)> = spans(line, width).map(|(a, b)| (width - b, width - a)).collect();
+    spans.reverse();
+    from_spans(spans, width)
+}
+
+/// Change the width of the line to `new_width`, padding with white or truncating.
+pub fn resize(line: &[u16], width: u16, new_width: u16) -> Vec<u16> {
+    from_spans(spans(line, width), new_width)
+}
+
+/// Move the line `offset` pels to the right (or to the left if negative).
+///
+/// Pels moved out of the line are dropped, the gap is filled with white.
+pub fn shift(line: &[u16], width: u16, offset: i32) -> Vec<u16> {
+    let spans = spans(line, width).filter_map(|(a, b)| {
+        let a = (a as i32 + offset).clamp(0, width as i32) as u16;
+        let b = (b as i32 + offset).clamp(0, width as i32) as u16;
+        if a < b { Some((a, b)) } else { None }
+    });
+    from_spans(spans, width)
+}
+
+fn combine(a: &[u16], b: &[u16], width: u16, op: impl Fn(bool, bool) -> bool) -> Vec<u16> {
+    let (a, b) = (normalize(a, width), normalize(b, width));
+    let (mut i, mut j) = (0, 0);
+    let (mut black_a, mut black_b) = (false, false);
+    let mut black = false;
+    let mut out = vec![];
+    loop {
+        let pos = match (a.get(i), b.get(j)) {
+            (Some(&x), Some(&y)) => x.min(y),
+            (Some(&x), None) => x,
+            (None, Some(&y)) => y,
+            (None, None) => break,
+        };
+        if a.get(i) == Some(&pos) {
+            black_a = !black_a;
+            i += 1;
+        }
+        if b.get(j) == Some(&pos) {
+            black_b = !black_b;
+            j += 1;
+        }
+        if op(black_a, black_b) != black {
+            black = !black;
+            out.push(pos);
+        }
+    }
+    out
+}
+
+/// Pels that are black in either line.
+pub fn or(a: &[u16], b: &[u16], width: u16) -> Vec<u16> {
+    combine(a, b, width, |a, b| a | b)
+}
+
+/// Pels that are black in both lines.
+pub fn and(a: &[u16], b: &[u16], width: u16) -> Vec<u16> {
+    combine(a, b, width, |a, b| a & b)
+}
+
+/// Pels that are black in exactly one of the lines.
+pub fn xor(a: &[u16], b: &[u16], width: u16) -> Vec<u16> {
+    combine(a, b, width, |a, b| a ^ b)
+}
+
+#[test]
+fn test_runs() {
+    fn pels(line: &[u16], width: u16) -> Vec<bool> {
+        (0 .. width).map(|x| is_black(line, width, x)).collect()
+    }
+    let width = 20;
+    let a = [0, 3, 7, 7, 9, 15, 25];
+    let b = [2, 10, 18];
+    let (pa, pb) = (pels(&a, width), pels(&b, width));
+    assert_eq!(normalize(&a, width), [0, 3, 9, 15]);
+    assert_eq!(normalize(&[3, 9, 5, 12, 25, 14], width), [3, 5, 9, 12, 14]);
+    assert_eq!(normalize(&[8, 4, 4, 8, 8], width), [8]);
+
+    assert_eq!(pels(&crop(&a, width, 2, 12), 10), pa[2 .. 12]);
+    assert_eq!(pels(&invert(&a, width), width), pa.iter().map(|&p| !p).collect::<Vec<_>>());
+    assert_eq!(pels(&mirror(&b, width), width), pb.iter().rev().cloned().collect::<Vec<_>>());
+    assert_eq!(pels(&resize(&b, width, 30), 30), [&pb[..], &[false; 10]].concat());
+    assert_eq!(pels(&resize(&b, width, 12), 12), pb[.. 12]);
+    assert_eq!(pels(&shift(&a, width, 4), width), [&[false; 4], &pa[.. 16]].concat());
+    assert_eq!(pels(&shift(&b, width, -5), width), [&pb[5 ..], &[false; 5]].concat());
+
+    let op = |f: fn(bool, bool) -> bool| pa.iter().zip(&pb).map(|(&x, &y)| f(x, y)).collect::<Vec<_>>();
+    assert_eq!(pels(&or(&a, &b, width), width), op(|x, y| x | y));
+    assert_eq!(pels(&and(&a, &b, width), width), op(|x, y| x & y));
+    assert_eq!(pels(&xor(&a, &b, width), width), op(|x, y| x ^ y));
+}
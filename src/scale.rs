@@ -0,0 +1,223 @@
+use crate::Coding;
+use crate::runs;
+use crate::transcode::{AnyEncoder, decode_any};
+
+/// A scale factor `num / den`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Ratio {
+    pub num: u32,
+    pub den: u32,
+}
+impl Ratio {
+    pub fn new(num: u32, den: u32) -> Self {
+        assert!(num > 0 && den > 0);
+        Ratio { num, den }
+    }
+    /// The ratio that scales `from` pels to `to` pels, for example an arbitrary
+    /// scanned width to the 1728 pels of an A4 fax line.
+    pub fn fit(from: u16, to: u16) -> Self {
+        Ratio::new(to as u32, from as u32)
+    }
+    fn apply(self, n: u32) -> u32 {
+        (n as u64 * self.num as u64 / self.den as u64) as u32
+    }
+}
+
+/// How the pels of the source that fall into one pel of the result are combined.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ScaleMode {
+    /// Black if any of them is black. Keeps thin strokes when reducing.
+    Or,
+    /// Black if they are mostly black (by area). Gives cleaner reductions of text and halftones.
+    Majority,
+}
+
+fn clamp_u16(n: u64) -> u16 {
+    n.min(u16::MAX as u64) as u16
+}
+
+/// Scale one line horizontally by `ratio`. The result has a width of `ratio` applied to `width`.
+pub fn scale_line(line: &[u16], width: u16, ratio: Ratio, mode: ScaleMode) -> Vec<u16> {
+    let new_width = ratio.apply(width as u32).min(u16::MAX as u32) as u16;
+    let (num, den) = (ratio.num as u64, ratio.den as u64);
+    // an input pel `i` covers `i * num .. (i + 1) * num`, an output pel `j` covers `j * den .. (j + 1) * den`
+    let spans = runs::spans(line, width).map(|(a, b)| (a as u64 * num, b as u64 * num));
+    match mode {
+        ScaleMode::Or => {
+            let spans = spans.map(|(s, e)| (clamp_u16(s / den), clamp_u16(e.div_ceil(den))));
+            runs::from_spans(spans, new_width)
+        }
+        ScaleMode::Majority => {
+            let mut out = vec![];
+            // output pel that is partially covered so far, with the covered area
+            let mut partial: Option<(u64, u64)> = None;
+            let flush = |out: &mut Vec<(u16, u16)>, (j, cov): (u64, u64)| {
+                if 2 * cov > den {
+                    out.push((clamp_u16(j), clamp_u16(j + 1)));
+                }
+            };
+            for (s, e) in spans {
+                let (first, last) = (s / den, (e - 1) / den);
+                let mut cov_first = (e.min((first + 1) * den)) - s;
+                match partial.take() {
+                    Some((j, cov)) if j == first => cov_first += cov,
+                    Some(p) => flush(&mut out, p),
+                    None => {}
+                }
+                if first == last {
+                    partial = Some((first, cov_first));
+                } else {
+                    flush(&mut out, (first, cov_first));
+                    if first + 1 < last {
+                        out.push((clamp_u16(first + 1), clamp_u16(last)));
+                    }
+                    partial = Some((last, e - last * den));
+                }
+            }
+            if let Some(p) = partial {
+                flush(&mut out, p);
+            }
+            runs::from_spans(out, new_width)
+        }
+    }
+}
+
+/// Scales a page, fed line by line as lists of color changes, by arbitrary ratios.
+///
+/// Each line is scaled horizontally with `scale_line`, then the lines are combined vertically
+/// with the same mode. The scaled lines are passed to `line_cb` as list of color changes,
+/// ready for `Encoder::encode_transitions`.
+pub struct Scaler<F> {
+    width: u16,
+    new_width: u16,
+    x: Ratio,
+    y: Ratio,
+    mode: ScaleMode,
+    /// number of input lines so far
+    lines: u32,
+    /// index of the output line being built
+    out: u32,
+    /// area of the output line covered by input lines so far
+    covered: u64,
+    /// `ScaleMode::Or`: OR of the lines
+    pending: Vec<u16>,
+    /// `ScaleMode::Majority`: black area per pel, as differences to the previous pel
+    votes: Vec<i64>,
+    line_cb: F,
+}
+impl<F: FnMut(&[u16])> Scaler<F> {
+    pub fn new(width: u16, x: Ratio, y: Ratio, mode: ScaleMode, line_cb: F) -> Self {
+        let new_width = x.apply(width as u32).min(u16::MAX as u32) as u16;
+        Scaler {
+            width,
+            new_width,
+            x,
+            y,
+            mode,
+            lines: 0,
+            out: 0,
+            covered: 0,
+            pending: vec![],
+            votes: vec![0; new_width as usize + 1],
+            line_cb,
+        }
+    }
+    /// Width of the output lines.
+    pub fn new_width(&self) -> u16 {
+        self.new_width
+    }
+    fn add(&mut self, line: &[u16], area: u64) {
+        self.covered += area;
+        match self.mode {
+            ScaleMode::Or => self.pending = runs::or(&self.pending, line, self.new_width),
+            ScaleMode::Majority => for (a, b) in runs::spans(line, self.new_width) {
+                self.votes[a as usize] += area as i64;
+                self.votes[b as usize] -= area as i64;
+            }
+        }
+    }
+    fn emit(&mut self) {
+        match self.mode {
+            ScaleMode::Or => {
+                (self.line_cb)(&self.pending);
+                self.pending.clear();
+            }
+            ScaleMode::Majority => {
+                let mut line = vec![];
+                let mut area = 0;
+                for (x, v) in self.votes.iter_mut().enumerate() {
+                    area += *v;
+                    *v = 0;
+                    if x < self.new_width as usize && (2 * area as u64 > self.covered) != (line.len() % 2 == 1) {
+                        line.push(x as u16);
+                    }
+                }
+                (self.line_cb)(&line);
+            }
+        }
+        self.covered = 0;
+        self.out += 1;
+    }
+    /// Add the next input line.
+    pub fn push(&mut self, line: &[u16]) {
+        let scaled = scale_line(line, self.width, self.x, self.mode);
+        let (num, den) = (self.y.num as u64, self.y.den as u64);
+        // in units where an input line is `num` high and an output line `den`
+        let (start, end) = (self.lines as u64 * num, (self.lines as u64 + 1) * num);
+        self.lines += 1;
+        loop {
+            let (out_start, out_end) = (self.out as u64 * den, (self.out as u64 + 1) * den);
+            let area = end.min(out_end) - start.max(out_start);
+            if out_end <= end {
+                self.add(&scaled, area);
+                self.emit();
+            } else {
+                if end > out_start {
+                    self.add(&scaled, area);
+                }
+                break;
+            }
+        }
+    }
+    /// Write the last, partially covered output line, if any.
+    pub fn finish(mut self) {
+        if self.covered > 0 {
+            self.emit();
+        }
+    }
+}
+
+/// Scale a raw coded stream by the given ratios, keeping the coding.
+///
+/// Returns the new data and the new width.
+pub fn scale(input: &[u8], width: u16, coding: Coding, x: Ratio, y: Ratio, mode: ScaleMode) -> Option<(Vec<u8>, u16)> {
+    let mut encoder = AnyEncoder::new(coding);
+    let new_width = x.apply(width as u32).min(u16::MAX as u32) as u16;
+    let mut scaler = Scaler::new(width, x, y, mode, |line: &[u16]| encoder.encode_transitions(line, new_width));
+    decode_any(input, width, coding, |line| scaler.push(line))?;
+    scaler.finish();
+    Some((encoder.finish(), new_width))
+}
+
+#[test]
+fn test_scale() {
+    let line = [0, 1, 4, 7, 9, 10];
+    assert_eq!(scale_line(&line, 12, Ratio::new(1, 3), ScaleMode::Or), [0]);
+    assert_eq!(scale_line(&line, 12, Ratio::new(1, 3), ScaleMode::Majority), [1, 2]);
+    assert_eq!(scale_line(&line, 12, Ratio::new(2, 1), ScaleMode::Majority), [0, 2, 8, 14, 18, 20]);
+    assert_eq!(scale_line(&[1, 2], 3, Ratio::fit(3, 2), ScaleMode::Majority), [] as [u16; 0]);
+    assert_eq!(scale_line(&[1, 2], 3, Ratio::fit(3, 2), ScaleMode::Or), [0]);
+
+    let lines: Vec<Vec<u16>> = vec![vec![0, 6], vec![0, 6], vec![0, 2], vec![], vec![4]];
+    let run = |mode| {
+        let mut out = vec![];
+        let mut scaler = Scaler::new(6, Ratio::new(1, 2), Ratio::new(1, 2), mode, |line: &[u16]| out.push(line.to_vec()));
+        for line in &lines {
+            scaler.push(line);
+        }
+        scaler.finish();
+        out
+    };
+    assert_eq!(run(ScaleMode::Or), [vec![0], vec![0, 1], vec![2]]);
+    assert_eq!(run(ScaleMode::Majority), [vec![0], vec![], vec![2]]);
+}
@@ -0,0 +1,643 @@
+use std::collections::VecDeque;
+use crate::Resolution;
+use crate::decoder::{decode_g3_checked, decode_g4, LineStats};
+use crate::resample::Resampler;
+use crate::runs;
+use crate::t30::{self, Dcs, Dis, EncoderConfig};
+use crate::transcode::AnyEncoder;
+use crate::Coding;
+
+/// Something sent over the line.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Signal {
+    /// A control frame: address, control field, FCF and FIF, as passed to `hdlc::HdlcEncoder::frame`.
+    Frame(Vec<u8>),
+    /// The training check, sent with the modem chosen in DCS.
+    Tcf(Vec<u8>),
+    /// The coded data of a page.
+    Page(Vec<u8>),
+}
+
+/// Phases of a T.30 session.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Phase {
+    /// A: the call is set up.
+    CallSetup,
+    /// B: capabilities are exchanged and the modem is trained.
+    PreMessage,
+    /// C: a page is transferred.
+    Message,
+    /// D: the page is confirmed.
+    PostMessage,
+    /// E: the call is released.
+    Release,
+}
+
+/// A page as list of lines, each given as list of color changes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Page {
+    pub width: u16,
+    pub resolution: Resolution,
+    pub lines: Vec<Vec<u16>>,
+}
+
+/// How often a command is repeated, or a page sent again, before giving up.
+const MAX_RETRIES: u8 = 3;
+
+/// The X bit, set in the FCF by the station that received DIS.
+const X: u8 = 0x01;
+
+fn frame(fcf: u8, fif: &[u8], last: bool) -> Signal {
+    let mut data = vec![0xFF, if last { 0x13 } else { 0x03 }, fcf];
+    data.extend_from_slice(fif);
+    Signal::Frame(data)
+}
+
+/// Split a frame into FCF (without the X bit) and FIF.
+fn parse_frame(data: &[u8]) -> Option<(u8, &[u8])> {
+    match data {
+        [0xFF, 0x03, fcf, fif @ ..] | [0xFF, 0x13, fcf, fif @ ..] => Some((fcf & !X, fif)),
+        _ => None,
+    }
+}
+
+/// The FIF of TSI or CSI: 20 characters in reverse order, padded with spaces.
+pub fn ident_fif(ident: &str) -> Vec<u8> {
+    let mut fif: Vec<u8> = ident.bytes().rev().take(20).collect();
+    fif.resize(20, b' ');
+    fif
+}
+
+pub fn parse_ident(fif: &[u8]) -> String {
+    let ident: String = fif.iter().rev().map(|&b| b as char).collect();
+    ident.trim().to_string()
+}
+
+/// The training check: 1.5 seconds of zeros.
+fn tcf(dcs: &Dcs) -> Vec<u8> {
+    vec![0; (dcs.modem.bit_rate() * 3 / 16) as usize]
+}
+
+/// A training check is good if it holds one second of zeros in a row.
+fn tcf_ok(dcs: &Dcs, data: &[u8]) -> bool {
+    let longest = data.split(|&b| b != 0).map(|run| run.len()).max().unwrap_or(0);
+    longest >= (dcs.modem.bit_rate() / 8) as usize
+}
+
+/// Code a page for the negotiated settings, changing its resolution and width as needed.
+pub fn encode_page(page: &Page, config: &EncoderConfig) -> Vec<u8> {
+    let mut lines = vec![];
+    let mut resampler = Resampler::new(page.resolution, config.resolution, page.width, |line: &[u16]| lines.push(line.to_vec()));
+    let width = resampler.new_width();
+    for line in &page.lines {
+        resampler.push(line);
+    }
+    resampler.finish();
+    let mut encoder = AnyEncoder::new(config.coding).with_min_scan_time(config.bit_rate, config.min_scan_time as u32);
+    for line in &lines {
+        encoder.encode_transitions(&runs::resize(line, width, config.width), config.width);
+    }
+    encoder.finish()
+}
+
+/// Decode a received page.
+pub fn decode_page(data: &[u8], config: &EncoderConfig) -> (Page, LineStats) {
+    let mut lines = vec![];
+    let stats = match config.coding {
+        Coding::Group4 => {
+            let ok = decode_g4(data.iter().cloned(), config.width, None, |line| lines.push(line.to_vec())).is_some();
+            // there is no EOL to continue at, so a damaged page is bad as a whole
+            let n = lines.len() as u32;
+            let bad = if ok { 0 } else { n + 1 };
+            LineStats { lines: n + !ok as u32, bad_lines: bad, consecutive_bad_lines: bad }
+        }
+        coding => {
+            let two_d = matches!(coding, Coding::Group3TwoD { .. });
+            decode_g3_checked(data.iter().cloned(), config.width, two_d, |line| lines.push(line.to_vec()))
+        }
+    };
+    (Page { width: config.width, resolution: config.resolution, lines }, stats)
+}
+
+/// Whether `dcs` only asks for what was offered in `local`. ECM is never accepted, as pages
+/// are not sent in ECM frames here.
+fn offered(local: &Dis, dcs: &Dcs) -> bool {
+    let coding = match dcs.coding {
+        Coding::Group3OneD => true,
+        Coding::Group3TwoD { .. } => local.two_d,
+        Coding::Group4 => local.t6,
+    };
+    let resolution = match dcs.resolution {
+        Resolution::FINE => local.fine,
+        Resolution::SUPERFINE => local.superfine,
+        Resolution::ULTRAFINE => local.ultrafine,
+        _ => true,
+    };
+    coding && resolution && dcs.ecm.is_none() && local.modems.supports(dcs.modem) && dcs.width <= local.max_width
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum SenderState {
+    WaitDis,
+    /// DCS and TCF were sent.
+    WaitTraining,
+    /// The page and the post message command (its FCF) were sent.
+    WaitResponse(u8),
+    Done,
+    Failed,
+}
+
+/// The transmitting station.
+///
+/// Feed everything received to `receive` and send what it returns. If nothing arrives in
+/// time, call `timeout`, which repeats the last command.
+pub struct Sender {
+    local: Dis,
+    ident: String,
+    pages: Vec<Page>,
+    /// index of the page being sent
+    page: usize,
+    remote: Option<Dis>,
+    remote_ident: Option<String>,
+    dcs: Option<Dcs>,
+    state: SenderState,
+    last: Vec<Signal>,
+    retries: u8,
+    page_retries: u8,
+}
+impl Sender {
+    pub fn new(local: Dis, ident: &str, pages: Vec<Page>) -> Self {
+        Sender {
+            local,
+            ident: ident.to_string(),
+            pages,
+            page: 0,
+            remote: None,
+            remote_ident: None,
+            dcs: None,
+            state: SenderState::WaitDis,
+            last: vec![],
+            retries: 0,
+            page_retries: 0,
+        }
+    }
+    pub fn phase(&self) -> Phase {
+        match self.state {
+            SenderState::WaitDis | SenderState::WaitTraining => Phase::PreMessage,
+            SenderState::WaitResponse(_) => Phase::PostMessage,
+            SenderState::Done | SenderState::Failed => Phase::Release,
+        }
+    }
+    pub fn failed(&self) -> bool {
+        self.state == SenderState::Failed
+    }
+    /// The settings of the last DCS.
+    pub fn dcs(&self) -> Option<&Dcs> {
+        self.dcs.as_ref()
+    }
+    /// The identification of the receiver (CSI).
+    pub fn remote_ident(&self) -> Option<&str> {
+        self.remote_ident.as_deref()
+    }
+    /// Number of pages confirmed by the receiver.
+    pub fn pages_sent(&self) -> usize {
+        self.page
+    }
+    fn send(&mut self, signals: Vec<Signal>) -> Vec<Signal> {
+        self.last = signals.clone();
+        self.retries = 0;
+        signals
+    }
+    fn disconnect(&mut self, state: SenderState) -> Vec<Signal> {
+        self.state = state;
+        vec![frame(t30::DCN | X, &[], true)]
+    }
+    fn train(&mut self) -> Vec<Signal> {
+        let dcs = self.dcs.as_ref().unwrap();
+        let signals = vec![
+            frame(t30::TSI | X, &ident_fif(&self.ident), false),
+            frame(t30::DCS | X, &dcs.to_fif(), true),
+            Signal::Tcf(tcf(dcs)),
+        ];
+        self.state = SenderState::WaitTraining;
+        self.send(signals)
+    }
+    fn send_page(&mut self) -> Vec<Signal> {
+        let page = &self.pages[self.page];
+        let data = encode_page(page, &self.dcs.as_ref().unwrap().encoder_config());
+        let post_message = match self.pages.get(self.page + 1) {
+            None => t30::EOP,
+            Some(next) if next.resolution == page.resolution && next.width == page.width => t30::MPS,
+            // different settings have to be negotiated again
+            Some(_) => t30::EOM,
+        };
+        self.state = SenderState::WaitResponse(post_message);
+        self.send(vec![Signal::Page(data), frame(post_message | X, &[], true)])
+    }
+    /// Choose the settings for the current page.
+    fn negotiate(&mut self) -> Vec<Signal> {
+        let page = &self.pages[self.page];
+        let remote = self.remote.as_ref().unwrap();
+        match Dcs::negotiate(remote, &self.local, page.resolution, page.width) {
+            Some(dcs) if remote.ready_to_receive => {
+                self.dcs = Some(dcs);
+                self.train()
+            }
+            _ => self.disconnect(SenderState::Failed),
+        }
+    }
+    pub fn receive(&mut self, signal: Signal) -> Vec<Signal> {
+        let data = match signal {
+            Signal::Frame(data) => data,
+            _ => return vec![],
+        };
+        let (fcf, fif) = match parse_frame(&data) {
+            Some(frame) => frame,
+            None => return vec![],
+        };
+        match (self.state, fcf) {
+            (SenderState::Done, _) | (SenderState::Failed, _) => vec![],
+            (_, t30::DCN) => {
+                self.state = SenderState::Failed;
+                vec![]
+            }
+            (_, t30::CSI) => {
+                self.remote_ident = Some(parse_ident(fif));
+                vec![]
+            }
+            (SenderState::WaitDis, t30::DIS) | (SenderState::WaitTraining, t30::DIS) => {
+                match Dis::parse(fif) {
+                    Some(dis) => self.remote = Some(dis),
+                    None => return self.disconnect(SenderState::Failed),
+                }
+                if self.pages.is_empty() {
+                    return self.disconnect(SenderState::Done);
+                }
+                self.negotiate()
+            }
+            (SenderState::WaitTraining, t30::CFR) => self.send_page(),
+            (SenderState::WaitTraining, t30::FTT) => {
+                let dcs = self.dcs.as_mut().unwrap();
+                match dcs.fallback(self.remote.as_ref().unwrap(), &self.local) {
+                    Some(modem) => {
+                        dcs.modem = modem;
+                        self.train()
+                    }
+                    None => self.disconnect(SenderState::Failed),
+                }
+            }
+            (SenderState::WaitResponse(post_message), t30::MCF) | (SenderState::WaitResponse(post_message), t30::RTP) => {
+                self.page += 1;
+                self.page_retries = 0;
+                match post_message {
+                    t30::EOP => self.disconnect(SenderState::Done),
+                    t30::EOM => {
+                        self.state = SenderState::WaitDis;
+                        self.send(vec![])
+                    }
+                    _ if fcf == t30::RTP => self.train(),
+                    _ => self.send_page(),
+                }
+            }
+            (SenderState::WaitResponse(_), t30::RTN) => {
+                self.page_retries += 1;
+                if self.page_retries > MAX_RETRIES {
+                    return self.disconnect(SenderState::Failed);
+                }
+                self.train()
+            }
+            _ => vec![],
+        }
+    }
+    /// No response arrived in time: repeat the last command (without page data).
+    pub fn timeout(&mut self) -> Vec<Signal> {
+        if matches!(self.state, SenderState::Done | SenderState::Failed) {
+            return vec![];
+        }
+        self.retries += 1;
+        if self.retries > MAX_RETRIES {
+            return self.disconnect(SenderState::Failed);
+        }
+        self.last.iter().filter(|s| !matches!(s, Signal::Page(_))).cloned().collect()
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum ReceiverState {
+    Idle,
+    /// DIS was sent, at the start or after EOM.
+    WaitDcs,
+    /// A retraining is expected.
+    WaitCommand,
+    WaitTcf,
+    WaitPage,
+    WaitPostMessage,
+    /// The last page was confirmed.
+    WaitDcn,
+    Done,
+    Failed,
+}
+
+/// The receiving (called) station. It is used like `Sender`, after calling `answer`.
+pub struct Receiver {
+    local: Dis,
+    ident: String,
+    remote_ident: Option<String>,
+    dcs: Option<Dcs>,
+    state: ReceiverState,
+    pages: Vec<Page>,
+    current: Option<(Page, LineStats)>,
+    /// the last post message command and the response to it
+    last: Option<(u8, Vec<Signal>)>,
+    retries: u8,
+}
+impl Receiver {
+    pub fn new(local: Dis, ident: &str) -> Self {
+        Receiver {
+            local,
+            ident: ident.to_string(),
+            remote_ident: None,
+            dcs: None,
+            state: ReceiverState::Idle,
+            pages: vec![],
+            current: None,
+            last: None,
+            retries: 0,
+        }
+    }
+    pub fn phase(&self) -> Phase {
+        match self.state {
+            ReceiverState::Idle => Phase::CallSetup,
+            ReceiverState::WaitDcs | ReceiverState::WaitCommand | ReceiverState::WaitTcf => Phase::PreMessage,
+            ReceiverState::WaitPage => Phase::Message,
+            ReceiverState::WaitPostMessage | ReceiverState::WaitDcn => Phase::PostMessage,
+            ReceiverState::Done | ReceiverState::Failed => Phase::Release,
+        }
+    }
+    pub fn failed(&self) -> bool {
+        self.state == ReceiverState::Failed
+    }
+    /// The identification of the sender (TSI).
+    pub fn remote_ident(&self) -> Option<&str> {
+        self.remote_ident.as_deref()
+    }
+    /// The pages received so far.
+    pub fn pages(&self) -> &[Page] {
+        &self.pages
+    }
+    pub fn into_pages(self) -> Vec<Page> {
+        self.pages
+    }
+    fn dis(&mut self) -> Vec<Signal> {
+        self.state = ReceiverState::WaitDcs;
+        vec![
+            frame(t30::CSI, &ident_fif(&self.ident), false),
+            frame(t30::DIS, &self.local.to_fif(), true),
+        ]
+    }
+    /// Answer the call, sending CSI and DIS.
+    pub fn answer(&mut self) -> Vec<Signal> {
+        self.dis()
+    }
+    /// The response to a post message command: whether the page was good enough.
+    fn post_message(&mut self, fcf: u8) -> Vec<Signal> {
+        let (page, stats) = self.current.take().unwrap();
+        let response = if stats.bad_lines == 0 {
+            t30::MCF
+        } else if stats.bad_lines * 20 <= stats.lines && stats.consecutive_bad_lines <= 5 {
+            t30::RTP
+        } else {
+            t30::RTN
+        };
+        if response != t30::RTN {
+            self.pages.push(page);
+        }
+        let mut signals = vec![frame(response, &[], true)];
+        match (fcf, response) {
+            (_, t30::RTN) => self.state = ReceiverState::WaitCommand,
+            (t30::EOP, _) => self.state = ReceiverState::WaitDcn,
+            (t30::MPS, t30::MCF) => self.state = ReceiverState::WaitPage,
+            // the next page needs new settings
+            (t30::EOM, _) => signals.extend(self.dis()),
+            _ => self.state = ReceiverState::WaitCommand,
+        }
+        self.last = Some((fcf, signals.clone()));
+        signals
+    }
+    pub fn receive(&mut self, signal: Signal) -> Vec<Signal> {
+        if matches!(self.state, ReceiverState::Done | ReceiverState::Failed) {
+            return vec![];
+        }
+        self.retries = 0;
+        match (self.state, signal) {
+            (ReceiverState::WaitTcf, Signal::Tcf(data)) => {
+                let dcs = self.dcs.as_ref().unwrap();
+                if tcf_ok(dcs, &data) {
+                    self.state = ReceiverState::WaitPage;
+                    vec![frame(t30::CFR, &[], true)]
+                } else {
+                    self.state = ReceiverState::WaitCommand;
+                    vec![frame(t30::FTT, &[], true)]
+                }
+            }
+            (ReceiverState::WaitPage, Signal::Page(data)) => {
+                let config = self.dcs.as_ref().unwrap().encoder_config();
+                self.current = Some(decode_page(&data, &config));
+                self.state = ReceiverState::WaitPostMessage;
+                vec![]
+            }
+            (_, Signal::Frame(data)) => {
+                let (fcf, fif) = match parse_frame(&data) {
+                    Some(frame) => frame,
+                    None => return vec![],
+                };
+                match (self.state, fcf) {
+                    (ReceiverState::WaitDcn, t30::DCN) => {
+                        self.state = ReceiverState::Done;
+                        vec![]
+                    }
+                    (_, t30::DCN) => {
+                        self.state = ReceiverState::Failed;
+                        vec![]
+                    }
+                    (_, t30::TSI) => {
+                        self.remote_ident = Some(parse_ident(fif));
+                        vec![]
+                    }
+                    (_, t30::DCS) => {
+                        match Dcs::parse(fif) {
+                            Some(dcs) if offered(&self.local, &dcs) => {
+                                self.dcs = Some(dcs);
+                                self.state = ReceiverState::WaitTcf;
+                                vec![]
+                            }
+                            // cannot handle it, so ask again
+                            _ => self.dis(),
+                        }
+                    }
+                    (ReceiverState::WaitPostMessage, t30::MPS)
+                    | (ReceiverState::WaitPostMessage, t30::EOM)
+                    | (ReceiverState::WaitPostMessage, t30::EOP) => self.post_message(fcf),
+                    // the response got lost, so the command is repeated
+                    (_, fcf) => match &self.last {
+                        Some((last, signals)) if *last == fcf => signals.clone(),
+                        _ => vec![],
+                    },
+                }
+            }
+            _ => vec![],
+        }
+    }
+    /// Nothing arrived in time. While waiting for DCS, CSI and DIS are repeated.
+    pub fn timeout(&mut self) -> Vec<Signal> {
+        if matches!(self.state, ReceiverState::Idle | ReceiverState::Done | ReceiverState::Failed) {
+            return vec![];
+        }
+        self.retries += 1;
+        if self.retries > MAX_RETRIES {
+            self.state = ReceiverState::Failed;
+            return vec![];
+        }
+        if self.state == ReceiverState::WaitDcs {
+            return self.dis();
+        }
+        vec![]
+    }
+}
+
+/// Which way a signal goes.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Direction {
+    ToReceiver,
+    ToSender,
+}
+
+/// Run a session between a sender and a receiver until both have released the call.
+///
+/// `line` sees each signal and may change it, or drop it by returning false.
+/// When nothing is on the line, the timeouts of the stations expire.
+pub fn loopback(sender: &mut Sender, receiver: &mut Receiver, mut line: impl FnMut(Direction, &mut Signal) -> bool) {
+    let mut to_sender: VecDeque<Signal> = receiver.answer().into();
+    let mut to_receiver: VecDeque<Signal> = VecDeque::new();
+    while sender.phase() != Phase::Release || receiver.phase() != Phase::Release {
+        if let Some(mut signal) = to_receiver.pop_front() {
+            if line(Direction::ToReceiver, &mut signal) {
+                to_sender.extend(receiver.receive(signal));
+            }
+        } else if let Some(mut signal) = to_sender.pop_front() {
+            if line(Direction::ToSender, &mut signal) {
+                to_receiver.extend(sender.receive(signal));
+            }
+        } else {
+            to_receiver.extend(sender.timeout());
+            if to_receiver.is_empty() {
+                to_sender.extend(receiver.timeout());
+            }
+        }
+    }
+}
+
+#[test]
+fn test_session() {
+    let page = |resolution, n: u16| Page {
+        width: 1728,
+        resolution,
+        lines: (0 .. 200).map(|y| if y % 10 < 3 { vec![] } else { vec![y + n, y + 2 * n, 1000, 1001] }).collect(),
+    };
+    let pages = vec![page(Resolution::FINE, 1), page(Resolution::FINE, 2), page(Resolution::STANDARD, 3)];
+    let local = Dis::default();
+    let mut sender = Sender::new(local.clone(), "+49 30 1234", pages.clone());
+    let mut receiver = Receiver::new(local, "+1 555 0100");
+
+    let (mut tcfs, mut mcfs, mut page_signals) = (0, 0, 0);
+    let mut modems = vec![];
+    loopback(&mut sender, &mut receiver, |direction, signal| {
+        match (direction, signal) {
+            (Direction::ToReceiver, Signal::Frame(data)) if data[2] == t30::DCS | X => {
+                modems.push(Dcs::parse(&data[3 ..]).unwrap().modem);
+            }
+            // the first training fails
+            (Direction::ToReceiver, Signal::Tcf(data)) => {
+                tcfs += 1;
+                if tcfs == 1 {
+                    let mid = data.len() / 2;
+                    data[mid] = 0x55;
+                }
+            }
+            // the first page is damaged
+            (Direction::ToReceiver, Signal::Page(data)) => {
+                page_signals += 1;
+                if page_signals == 1 {
+                    for i in (0 .. data.len()).step_by(40) {
+                        data[i] ^= 0x18;
+                    }
+                }
+            }
+            // the confirmation of the second page gets lost
+            (Direction::ToSender, Signal::Frame(data)) if data[2] == t30::MCF => {
+                mcfs += 1;
+                return mcfs != 2;
+            }
+            _ => {}
+        }
+        true
+    });
+    assert!(!sender.failed() && !receiver.failed());
+    assert_eq!(sender.pages_sent(), 3);
+    assert_eq!(sender.remote_ident(), Some("+1 555 0100"));
+    assert_eq!(receiver.remote_ident(), Some("+49 30 1234"));
+    // fallback after the failed training, retraining after the damaged page, and
+    // a new negotiation after EOM
+    use t30::Modem::*;
+    assert_eq!(modems, [V17_14400, V17_12000, V17_12000, V17_14400]);
+    assert_eq!(sender.dcs().unwrap().resolution, Resolution::STANDARD);
+    assert_eq!(tcfs, 4);
+    assert_eq!(page_signals, 4);
+    assert_eq!(receiver.into_pages(), pages);
+
+    // after EOM the receiver sends DIS again, also if the page was only partly good (RTP),
+    // and repeats it if it gets lost
+    let pages = vec![page(Resolution::FINE, 1), page(Resolution::STANDARD, 2)];
+    for &(damage, drop_dis) in &[(true, false), (false, true)] {
+        let mut sender = Sender::new(Dis::default(), "", pages.clone());
+        let mut receiver = Receiver::new(Dis::default(), "");
+        let (mut dis, mut rtp) = (0, 0);
+        loopback(&mut sender, &mut receiver, |direction, signal| {
+            match (direction, signal) {
+                (Direction::ToReceiver, Signal::Page(data)) if damage && rtp == 0 => {
+                    let mid = data.len() / 2;
+                    data[mid .. mid + 4].fill(0xFF);
+                }
+                (Direction::ToSender, Signal::Frame(data)) if data[2] == t30::RTP => rtp += 1,
+                (Direction::ToSender, Signal::Frame(data)) if data[2] == t30::DIS => {
+                    dis += 1;
+                    return !(drop_dis && dis == 2);
+                }
+                _ => {}
+            }
+            true
+        });
+        assert!(!sender.failed() && !receiver.failed());
+        assert_eq!((sender.pages_sent(), rtp, dis), (2, damage as u32, 2 + drop_dis as u32));
+        assert_eq!(receiver.pages()[1], pages[1]);
+    }
+
+    // T.6 (with ECM) was not offered, so DIS is sent again
+    let mut receiver = Receiver::new(Dis::default(), "");
+    receiver.answer();
+    let dcs = Dcs::negotiate(&Dis::default(), &Dis::default(), Resolution::FINE, 1728).unwrap();
+    let t6 = Dcs { coding: Coding::Group4, ecm: Some(crate::ecm::FrameSize::Octets256), ..dcs.clone() };
+    let fcfs: Vec<u8> = receiver.receive(frame(t30::DCS | X, &t6.to_fif(), true)).iter()
+        .filter_map(|signal| match signal {
+            Signal::Frame(data) => parse_frame(data).map(|(fcf, _)| fcf),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(fcfs, [t30::CSI, t30::DIS]);
+    assert!(receiver.receive(frame(t30::DCS | X, &dcs.to_fif(), true)).is_empty());
+
+    // pages coded with T.6 are decoded as such
+    let config = t6.encoder_config();
+    let (page, stats) = decode_page(&encode_page(&pages[0], &config), &config);
+    assert_eq!((page.lines, stats.bad_lines), (pages[0].lines.clone(), 0));
+}
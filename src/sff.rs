@@ -0,0 +1,285 @@
+use std::convert::TryFrom;
+use crate::{BitReader, ByteReader, Coding, Resolution, VecWriter};
+use crate::decoder::{decode_1d_line, LineStats};
+use crate::encoder::{encode_1d_line, Encoder};
+use crate::runs;
+use crate::tiff::{self, Image, Options, Photometric};
+
+/// A page of a Structured Fax File, as list of lines.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Page {
+    pub width: u16,
+    pub resolution: Resolution,
+    /// Lines as list of color changes, starting with white.
+    pub lines: Vec<Vec<u16>>,
+    /// Lines marked as bad in the file or that could not be decoded. They were replaced
+    /// by the previous line.
+    pub stats: LineStats,
+}
+
+const SIGNATURE: &[u8] = b"Sfff";
+const HEADER_LEN: usize = 20;
+const PAGE_HEADER: u8 = 254;
+/// Lines with more coded bytes follow an escape and their length.
+const MAX_SHORT_LINE: usize = 216;
+/// Records 217 to 253 stand for 1 to 37 white lines.
+const MAX_WHITE_SKIP: usize = 37;
+
+fn vertical_resolution(code: u8) -> Option<u16> {
+    match code {
+        0 => Some(98),
+        1 => Some(196),
+        2 => Some(391),
+        _ => None,
+    }
+}
+
+/// Read all pages of an SFF file.
+///
+/// The lines are MH coded with the least significant bit first and without EOL.
+/// User information records are skipped.
+pub fn read(data: &[u8]) -> Option<Vec<Page>> {
+    let u16_at = |pos: usize| data.get(pos .. pos + 2).map(|b| u16::from_le_bytes([b[0], b[1]]));
+    if data.get(.. 4)? != SIGNATURE || *data.get(4)? != 1 {
+        return None;
+    }
+    let mut pos = u16_at(10)? as usize;
+    let mut pages = vec![];
+    loop {
+        if *data.get(pos)? != PAGE_HEADER {
+            return None;
+        }
+        let len = *data.get(pos + 1)? as usize;
+        if len == 0 {
+            return Some(pages);
+        }
+        let header = data.get(pos + 2 .. pos + 2 + len)?;
+        if header.len() < 8 || header[2] != 0 {
+            // only MH coding is defined
+            return None;
+        }
+        let y = vertical_resolution(header[0])?;
+        let x = match header[1] {
+            0 => 204,
+            _ => return None,
+        };
+        let width = u16::from_le_bytes([header[4], header[5]]);
+        pos += 2 + len;
+
+        let mut page = Page { width, resolution: Resolution { x, y }, lines: vec![], stats: LineStats::default() };
+        let mut consecutive = 0;
+        let mut bad = |page: &mut Page, is_bad: bool| {
+            page.stats.lines += 1;
+            if is_bad {
+                page.stats.bad_lines += 1;
+                consecutive += 1;
+                page.stats.consecutive_bad_lines = page.stats.consecutive_bad_lines.max(consecutive);
+                let last = page.lines.last().cloned().unwrap_or_default();
+                page.lines.push(last);
+            } else {
+                consecutive = 0;
+            }
+        };
+        loop {
+            let record = *data.get(pos)?;
+            let coded = match record {
+                PAGE_HEADER => break,
+                0 => {
+                    let len = u16_at(pos + 1)? as usize;
+                    pos += 3;
+                    Some(len)
+                }
+                1 ..= 216 => {
+                    pos += 1;
+                    Some(record as usize)
+                }
+                217 ..= 253 => {
+                    for _ in 216 .. record {
+                        page.lines.push(vec![]);
+                        bad(&mut page, false);
+                    }
+                    pos += 1;
+                    None
+                }
+                _ => {
+                    match *data.get(pos + 1)? {
+                        0 => bad(&mut page, true),
+                        n => pos += n as usize,
+                    }
+                    pos += 2;
+                    None
+                }
+            };
+            if let Some(len) = coded {
+                let bytes = data.get(pos .. pos + len)?;
+                pos += len;
+                // codes are looked up with up to 13 bits, also at the end of the line
+                let mut reader = ByteReader::new(bytes.iter().map(|b| b.reverse_bits()).chain([0, 0]));
+                let mut line = vec![];
+                let ok = decode_1d_line(&mut reader, &mut line, width).is_some()
+                    && reader.peek(reader.bits_to_byte_boundary()).is_some_and(|fill| fill == 0);
+                if ok {
+                    page.lines.push(line);
+                }
+                bad(&mut page, !ok);
+            }
+        }
+        pages.push(page);
+    }
+}
+
+/// The offsets to the previous and next page are left 0.
+fn write_page_header(out: &mut Vec<u8>, page: &Page) -> Option<()> {
+    let y = match page.resolution {
+        Resolution { x: 204, y: 98 } => 0,
+        Resolution { x: 204, y: 196 } => 1,
+        Resolution { x: 204, y: 391 } => 2,
+        _ => return None,
+    };
+    out.extend_from_slice(&[PAGE_HEADER, 16, y, 0, 0, 0]);
+    out.extend_from_slice(&page.width.to_le_bytes());
+    out.extend_from_slice(&u16::try_from(page.lines.len()).ok()?.to_le_bytes());
+    out.extend_from_slice(&[0; 8]);
+    Some(())
+}
+
+fn white_skip(out: &mut Vec<u8>, lines: usize) {
+    for n in (0 .. lines).step_by(MAX_WHITE_SKIP) {
+        out.push(216 + (lines - n).min(MAX_WHITE_SKIP) as u8);
+    }
+}
+
+/// Write pages into an SFF file.
+///
+/// White lines are written as white skip records. Fails if a page does not use one of the
+/// resolutions 204x98, 204x196 or 204x391, or has more than 65535 lines.
+pub fn write(pages: &[Page]) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(HEADER_LEN);
+    out.extend_from_slice(SIGNATURE);
+    out.extend_from_slice(&[1, 0, 0, 0]);
+    out.extend_from_slice(&u16::try_from(pages.len()).ok()?.to_le_bytes());
+    out.extend_from_slice(&(HEADER_LEN as u16).to_le_bytes());
+    // offsets of the last page and the end of the document are filled in below
+    out.extend_from_slice(&[0; 8]);
+
+    let mut headers = vec![];
+    for page in pages {
+        headers.push(out.len());
+        write_page_header(&mut out, page)?;
+        let mut white = 0;
+        for line in &page.lines {
+            let line = runs::normalize(line, page.width);
+            if line.is_empty() {
+                white += 1;
+                continue;
+            }
+            white_skip(&mut out, white);
+            white = 0;
+            let mut writer = VecWriter::new();
+            encode_1d_line(&mut writer, &line, page.width);
+            let coded = writer.finish();
+            if coded.len() <= MAX_SHORT_LINE {
+                out.push(coded.len() as u8);
+            } else {
+                out.push(0);
+                out.extend_from_slice(&u16::try_from(coded.len()).ok()?.to_le_bytes());
+            }
+            out.extend(coded.iter().map(|b| b.reverse_bits()));
+        }
+        white_skip(&mut out, white);
+    }
+    let end = out.len();
+    out.extend_from_slice(&[PAGE_HEADER, 0]);
+
+    // offsets relative to the page header, 1 if there is no such page
+    for (i, &header) in headers.iter().enumerate() {
+        let previous = if i == 0 { 1 } else { (header - headers[i - 1]) as u32 };
+        let next = headers.get(i + 1).map_or(1, |&next| (next - header) as u32);
+        out[header + 10 .. header + 14].copy_from_slice(&previous.to_le_bytes());
+        out[header + 14 .. header + 18].copy_from_slice(&next.to_le_bytes());
+    }
+    let last = headers.last().map_or(0, |&h| h as u32);
+    out[12 .. 16].copy_from_slice(&last.to_le_bytes());
+    out[16 .. 20].copy_from_slice(&(end as u32).to_le_bytes());
+    Some(out)
+}
+
+/// Convert all pages of an SFF file into a Group 4 TIFF.
+pub fn sff_to_tiff(data: &[u8], options: Options) -> Option<Vec<u8>> {
+    let images: Vec<Image> = read(data)?.iter().map(|page| {
+        let mut encoder = Encoder::new(VecWriter::new());
+        for line in &page.lines {
+            encoder.encode_transitions(line, page.width);
+        }
+        Image {
+            width: page.width as u32,
+            height: page.lines.len() as u32,
+            coding: Coding::Group4,
+            photometric: Photometric::WhiteIsZero,
+            resolution: Some(page.resolution),
+            rows_per_strip: page.lines.len() as u32,
+            strips: vec![encoder.finish().finish()],
+        }
+    }).collect();
+    Some(tiff::write(&images, options))
+}
+
+/// Convert all pages of a TIFF file into SFF.
+///
+/// Images without a resolution are taken to be in standard resolution.
+pub fn tiff_to_sff(data: &[u8]) -> Option<Vec<u8>> {
+    let mut pages = vec![];
+    for image in tiff::read(data)? {
+        let width = u16::try_from(image.width).ok()?;
+        let mut lines = vec![];
+        image.decode(|line| match image.photometric {
+            Photometric::WhiteIsZero => lines.push(line.to_vec()),
+            Photometric::BlackIsZero => lines.push(runs::invert(line, width)),
+        })?;
+        let resolution = image.resolution.unwrap_or(Resolution::STANDARD);
+        pages.push(Page { width, resolution, lines, stats: LineStats::default() });
+    }
+    write(&pages)
+}
+
+#[test]
+fn test_sff() {
+    let lines = |n: u16| -> Vec<Vec<u16>> {
+        (0 .. 100).map(|y| match y % 50 {
+            // a long line needs the escape
+            0 => (0 .. 860).map(|x| x * 2 + 1).collect(),
+            1 ..= 40 => vec![],
+            _ => vec![y * n, y * n + 10],
+        }).collect()
+    };
+    let pages = vec![
+        Page { width: 1728, resolution: Resolution::FINE, lines: lines(3), stats: LineStats::default() },
+        Page { width: 2048, resolution: Resolution::STANDARD, lines: lines(5), stats: LineStats::default() },
+    ];
+    let data = write(&pages).unwrap();
+    assert_eq!(&data[.. 12], b"Sfff\x01\x00\x00\x00\x02\x00\x14\x00");
+    assert_eq!(&data[20 .. 26], &[254, 16, 1, 0, 0, 0]);
+    assert_eq!(&data[data.len() - 2 ..], &[254, 0]);
+    let read_pages = read(&data).unwrap();
+    assert_eq!(read_pages.iter().map(|p| p.stats.lines).collect::<Vec<_>>(), [100, 100]);
+    assert_eq!(read_pages, pages.iter().map(|p| Page { stats: LineStats { lines: 100, ..p.stats }, ..p.clone() }).collect::<Vec<_>>());
+
+    // 40 white lines take two records, the escaped line 3 + 216 + 1 bytes
+    let first = 20 + 18;
+    assert_eq!(data[first], 0);
+    let len = u16::from_le_bytes([data[first + 1], data[first + 2]]) as usize;
+    assert!(len > MAX_SHORT_LINE);
+    assert_eq!(&data[first + 3 + len .. first + 5 + len], &[253, 219]);
+
+    // a bad line record and user information
+    let mut damaged = data[.. first].to_vec();
+    damaged.extend_from_slice(&[255, 0, 255, 3, b'a', b'b', b'c', 218]);
+    damaged.extend_from_slice(&data[first ..]);
+    let page = &read(&damaged).unwrap()[0];
+    assert_eq!(page.stats.bad_lines, 1);
+    assert_eq!(&page.lines[.. 3], &[vec![], vec![], vec![]]);
+
+    let tiff = sff_to_tiff(&data, Options::default()).unwrap();
+    assert_eq!(tiff::read(&tiff).unwrap().len(), 2);
+    assert_eq!(tiff_to_sff(&tiff).unwrap(), data);
+}
@@ -0,0 +1,506 @@
+use crate::{Coding, Resolution};
+use crate::ecm::FrameSize;
+
+/// Facsimile control field of the digital identification signal.
+pub const DIS: u8 = 0x80;
+/// Facsimile control field of the digital transmit command (DIS of a polling station).
+pub const DTC: u8 = 0x81;
+/// Facsimile control field of the digital command signal.
+pub const DCS: u8 = 0x82;
+
+// Other facsimile control fields, without the X bit. It is set in frames of the station
+// that received DIS.
+pub const CSI: u8 = 0x40;
+pub const TSI: u8 = 0x42;
+pub const CFR: u8 = 0x84;
+pub const FTT: u8 = 0x44;
+pub const MPS: u8 = 0x4E;
+pub const EOM: u8 = 0x8E;
+pub const EOP: u8 = 0x2E;
+pub const MCF: u8 = 0x8C;
+pub const RTP: u8 = 0xCC;
+pub const RTN: u8 = 0x4C;
+pub const DCN: u8 = 0xFA;
+
+/// Bit `n` of a FIF, counting from 1 with the least significant bit of the first octet.
+/// Bits beyond the end are not set.
+fn bit(fif: &[u8], n: usize) -> bool {
+    fif.get((n - 1) / 8).is_some_and(|&b| b & (1 << ((n - 1) % 8)) != 0)
+}
+
+/// The bits `first .. first + len`, the first one being the most significant.
+fn field(fif: &[u8], first: usize, len: usize) -> u8 {
+    (first .. first + len).fold(0, |v, n| v << 1 | bit(fif, n) as u8)
+}
+
+/// Collects the bits of a FIF and adds the extension bits.
+struct FifWriter {
+    fif: Vec<u8>,
+}
+impl FifWriter {
+    fn new() -> Self {
+        FifWriter { fif: vec![0; 3] }
+    }
+    fn set(&mut self, n: usize, value: bool) {
+        if !value {
+            return;
+        }
+        let i = (n - 1) / 8;
+        if i >= self.fif.len() {
+            self.fif.resize(i + 1, 0);
+        }
+        self.fif[i] |= 1 << ((n - 1) % 8);
+    }
+    fn set_field(&mut self, first: usize, len: usize, value: u8) {
+        for i in 0 .. len {
+            self.set(first + i, value >> (len - 1 - i) & 1 != 0);
+        }
+    }
+    /// The extend bit (the last one of each octet from the third) is set if another octet follows.
+    fn finish(mut self) -> Vec<u8> {
+        let last = self.fif.len() - 1;
+        for b in &mut self.fif[2 .. last] {
+            *b |= 0x80;
+        }
+        self.fif
+    }
+}
+
+/// Modems for the image data, with their data signalling rate.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Modem {
+    V27ter2400,
+    V27ter4800,
+    V29_7200,
+    V29_9600,
+    V17_7200,
+    V17_9600,
+    V17_12000,
+    V17_14400,
+}
+impl Modem {
+    pub fn bit_rate(self) -> u32 {
+        match self {
+            Modem::V27ter2400 => 2400,
+            Modem::V27ter4800 => 4800,
+            Modem::V29_7200 | Modem::V17_7200 => 7200,
+            Modem::V29_9600 | Modem::V17_9600 => 9600,
+            Modem::V17_12000 => 12000,
+            Modem::V17_14400 => 14400,
+        }
+    }
+    /// Bits 11 to 14 of DCS.
+    fn bits(self) -> u8 {
+        match self {
+            Modem::V27ter2400 => 0b0000,
+            Modem::V27ter4800 => 0b0100,
+            Modem::V29_9600 => 0b1000,
+            Modem::V29_7200 => 0b1100,
+            Modem::V17_14400 => 0b0001,
+            Modem::V17_12000 => 0b0101,
+            Modem::V17_9600 => 0b1001,
+            Modem::V17_7200 => 0b1101,
+        }
+    }
+}
+
+/// The modems a station offers in DIS.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Modems {
+    /// V.27 ter at 2400 bit/s only.
+    V27terFallback,
+    V27ter,
+    V29,
+    V27terV29,
+    V27terV29V17,
+}
+impl Modems {
+    pub fn supports(self, modem: Modem) -> bool {
+        use Modem::*;
+        let (v27ter, v29, v17) = match self {
+            Modems::V27terFallback => return modem == V27ter2400,
+            Modems::V27ter => (true, false, false),
+            Modems::V29 => (false, true, false),
+            Modems::V27terV29 => (true, true, false),
+            Modems::V27terV29V17 => (true, true, true),
+        };
+        match modem {
+            V27ter2400 | V27ter4800 => v27ter,
+            V29_7200 | V29_9600 => v29,
+            _ => v17,
+        }
+    }
+    /// Bits 11 to 14 of DIS.
+    fn bits(self) -> u8 {
+        match self {
+            Modems::V27terFallback => 0b0000,
+            Modems::V27ter => 0b0100,
+            Modems::V29 => 0b1000,
+            Modems::V27terV29 => 0b1100,
+            Modems::V27terV29V17 => 0b1101,
+        }
+    }
+    fn from_bits(bits: u8) -> Option<Self> {
+        [Modems::V27terFallback, Modems::V27ter, Modems::V29, Modems::V27terV29, Modems::V27terV29V17]
+            .iter().cloned().find(|m| m.bits() == bits)
+    }
+}
+
+/// Maximum recording length.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Length {
+    A4,
+    B4,
+    Unlimited,
+}
+impl Length {
+    fn bits(self) -> u8 {
+        match self {
+            Length::A4 => 0b00,
+            Length::Unlimited => 0b01,
+            Length::B4 => 0b10,
+        }
+    }
+    fn from_bits(bits: u8) -> Option<Self> {
+        match bits {
+            0b00 => Some(Length::A4),
+            0b01 => Some(Length::Unlimited),
+            0b10 => Some(Length::B4),
+            _ => None,
+        }
+    }
+}
+
+/// Minimum scan line time in milliseconds: for a line of 3.85 lines/mm, and whether lines of
+/// higher resolutions may take half of it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ScanTime {
+    pub ms: u8,
+    pub half_at_fine: bool,
+}
+impl ScanTime {
+    const CODES: [(u8, u8, bool); 8] = [
+        (0b000, 20, false), (0b001, 40, false), (0b010, 10, false), (0b100, 5, false),
+        (0b011, 10, true), (0b110, 20, true), (0b101, 40, true), (0b111, 0, false),
+    ];
+    fn bits(self) -> u8 {
+        ScanTime::CODES.iter()
+            .find(|&&(_, ms, half)| ms == self.ms && half == self.half_at_fine)
+            .map_or(0b000, |c| c.0)
+    }
+    fn from_bits(bits: u8) -> Self {
+        let &(_, ms, half_at_fine) = ScanTime::CODES.iter().find(|c| c.0 == bits).unwrap();
+        ScanTime { ms, half_at_fine }
+    }
+}
+
+/// Bits 17 and 18: the recording width (in DIS the widest one).
+fn width_bits(width: u16) -> u8 {
+    match width {
+        2048 => 0b10,
+        2432 => 0b01,
+        _ => 0b00,
+    }
+}
+
+fn width_from_bits(bits: u8) -> Option<u16> {
+    match bits {
+        0b00 => Some(1728),
+        0b10 => Some(2048),
+        0b01 => Some(2432),
+        _ => None,
+    }
+}
+
+/// Capabilities sent in DIS or DTC.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Dis {
+    /// Documents are ready to be polled.
+    pub ready_to_transmit: bool,
+    pub ready_to_receive: bool,
+    pub modems: Modems,
+    /// 8 pels/mm x 7.7 lines/mm
+    pub fine: bool,
+    /// 8 pels/mm x 15.4 lines/mm
+    pub superfine: bool,
+    /// 16 pels/mm x 15.4 lines/mm
+    pub ultrafine: bool,
+    /// Two-dimensional coding (MR).
+    pub two_d: bool,
+    /// T.6 coding (MMR), which requires ECM.
+    pub t6: bool,
+    pub uncompressed: bool,
+    pub ecm: bool,
+    /// The widest page: 1728, 2048 or 2432 pels.
+    pub max_width: u16,
+    pub length: Length,
+    pub scan_time: ScanTime,
+}
+impl Default for Dis {
+    fn default() -> Self {
+        Dis {
+            ready_to_transmit: false,
+            ready_to_receive: true,
+            modems: Modems::V27terV29V17,
+            fine: true,
+            superfine: false,
+            ultrafine: false,
+            two_d: true,
+            t6: false,
+            uncompressed: false,
+            ecm: false,
+            max_width: 1728,
+            length: Length::Unlimited,
+            scan_time: ScanTime { ms: 0, half_at_fine: false },
+        }
+    }
+}
+impl Dis {
+    /// Parse the FIF of DIS or DTC. Octets after the last one present count as zero.
+    pub fn parse(fif: &[u8]) -> Option<Self> {
+        if fif.len() < 3 {
+            return None;
+        }
+        Some(Dis {
+            ready_to_transmit: bit(fif, 9),
+            ready_to_receive: bit(fif, 10),
+            modems: Modems::from_bits(field(fif, 11, 4))?,
+            fine: bit(fif, 15),
+            superfine: bit(fif, 41),
+            ultrafine: bit(fif, 43),
+            two_d: bit(fif, 16),
+            t6: bit(fif, 31),
+            uncompressed: bit(fif, 26),
+            ecm: bit(fif, 27),
+            max_width: width_from_bits(field(fif, 17, 2))?,
+            length: Length::from_bits(field(fif, 19, 2))?,
+            scan_time: ScanTime::from_bits(field(fif, 21, 3)),
+        })
+    }
+    pub fn to_fif(&self) -> Vec<u8> {
+        let mut fif = FifWriter::new();
+        fif.set(9, self.ready_to_transmit);
+        fif.set(10, self.ready_to_receive);
+        fif.set_field(11, 4, self.modems.bits());
+        fif.set(15, self.fine);
+        fif.set(16, self.two_d);
+        fif.set_field(17, 2, width_bits(self.max_width));
+        fif.set_field(19, 2, self.length.bits());
+        fif.set_field(21, 3, self.scan_time.bits());
+        fif.set(26, self.uncompressed);
+        fif.set(27, self.ecm);
+        fif.set(31, self.t6);
+        fif.set(41, self.superfine);
+        fif.set(43, self.ultrafine);
+        // resolutions are metric
+        fif.set(45, self.superfine || self.ultrafine);
+        fif.finish()
+    }
+}
+
+/// Settings chosen by the transmitter in DCS.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Dcs {
+    pub modem: Modem,
+    pub resolution: Resolution,
+    /// One of `Group3OneD`, `Group3TwoD` (the `k` parameter follows from the resolution)
+    /// or `Group4`, which requires ECM.
+    pub coding: Coding,
+    pub uncompressed: bool,
+    pub ecm: Option<FrameSize>,
+    /// Page width at 8 pels/mm: 1728, 2048 or 2432 pels.
+    pub width: u16,
+    pub length: Length,
+    /// Minimum scan line time in milliseconds: 0, 5, 10, 20 or 40.
+    pub scan_time: u8,
+}
+
+/// All modems, the preferred ones first, in the order of falling back.
+const MODEMS: [Modem; 8] = [
+    Modem::V17_14400, Modem::V17_12000, Modem::V17_9600, Modem::V29_9600,
+    Modem::V17_7200, Modem::V29_7200, Modem::V27ter4800, Modem::V27ter2400,
+];
+
+impl Dcs {
+    pub fn parse(fif: &[u8]) -> Option<Self> {
+        if fif.len() < 3 {
+            return None;
+        }
+        let modem = MODEMS.iter().cloned().find(|m| m.bits() == field(fif, 11, 4))?;
+        let resolution = if bit(fif, 43) {
+            Resolution::ULTRAFINE
+        } else if bit(fif, 41) {
+            Resolution::SUPERFINE
+        } else if bit(fif, 15) {
+            Resolution::FINE
+        } else {
+            Resolution::STANDARD
+        };
+        let ecm = match (bit(fif, 27), bit(fif, 28)) {
+            (false, _) => None,
+            (true, false) => Some(FrameSize::Octets256),
+            (true, true) => Some(FrameSize::Octets64),
+        };
+        let coding = if bit(fif, 31) {
+            Coding::Group4
+        } else if bit(fif, 16) {
+            Coding::Group3TwoD { k: k_factor(resolution) }
+        } else {
+            Coding::Group3OneD
+        };
+        let scan_time = ScanTime::from_bits(field(fif, 21, 3));
+        if scan_time.half_at_fine {
+            return None;
+        }
+        Some(Dcs {
+            modem,
+            resolution,
+            coding,
+            uncompressed: bit(fif, 26),
+            ecm,
+            width: width_from_bits(field(fif, 17, 2))?,
+            length: Length::from_bits(field(fif, 19, 2))?,
+            scan_time: scan_time.ms,
+        })
+    }
+    pub fn to_fif(&self) -> Vec<u8> {
+        let mut fif = FifWriter::new();
+        fif.set(10, true);
+        fif.set_field(11, 4, self.modem.bits());
+        let r = self.resolution;
+        fif.set(15, r == Resolution::FINE);
+        fif.set(41, r == Resolution::SUPERFINE);
+        fif.set(43, r == Resolution::ULTRAFINE);
+        fif.set(16, matches!(self.coding, Coding::Group3TwoD { .. }));
+        fif.set(31, self.coding == Coding::Group4);
+        fif.set_field(17, 2, width_bits(self.width));
+        fif.set_field(19, 2, self.length.bits());
+        fif.set_field(21, 3, ScanTime { ms: self.scan_time, half_at_fine: false }.bits());
+        fif.set(26, self.uncompressed);
+        fif.set(27, self.ecm.is_some());
+        fif.set(28, self.ecm == Some(FrameSize::Octets64));
+        fif.finish()
+    }
+    /// Choose the settings for sending a page of the given resolution and width to a station
+    /// with the capabilities `remote`, using the fastest modem both offer.
+    ///
+    /// The resolution is lowered and the width reduced to what the receiver supports.
+    /// ECM is not used. Fails if there is no common modem.
+    pub fn negotiate(remote: &Dis, local: &Dis, resolution: Resolution, width: u16) -> Option<Dcs> {
+        let modem = MODEMS.iter().cloned().find(|&m| remote.modems.supports(m) && local.modems.supports(m))?;
+        // the width of the page at 8 pels/mm
+        let width = (width as u32 * 204 / resolution.x as u32) as u16;
+        let resolution = [
+            (Resolution::ULTRAFINE, remote.ultrafine && local.ultrafine),
+            (Resolution::SUPERFINE, remote.superfine && local.superfine),
+            (Resolution::FINE, remote.fine && local.fine),
+        ].iter()
+            .find(|&&(r, supported)| supported && r.x <= resolution.x && r.y <= resolution.y)
+            .map_or(Resolution::STANDARD, |r| r.0);
+        let two_d = remote.two_d && local.two_d;
+        let max_width = remote.max_width.min(local.max_width);
+        let width = [1728, 2048, 2432].iter().cloned()
+            .find(|&w| w >= width && w <= max_width)
+            .unwrap_or(max_width);
+        let scan_time = remote.scan_time;
+        Some(Dcs {
+            modem,
+            resolution,
+            coding: if two_d { Coding::Group3TwoD { k: k_factor(resolution) } } else { Coding::Group3OneD },
+            uncompressed: false,
+            ecm: None,
+            width,
+            length: remote.length,
+            scan_time: if scan_time.half_at_fine && resolution != Resolution::STANDARD { scan_time.ms / 2 } else { scan_time.ms },
+        })
+    }
+    /// The next slower modem both stations support, to fall back to after a failed training.
+    pub fn fallback(&self, remote: &Dis, local: &Dis) -> Option<Modem> {
+        MODEMS.iter().cloned()
+            .skip_while(|&m| m != self.modem)
+            .skip(1)
+            .find(|&m| remote.modems.supports(m) && local.modems.supports(m))
+    }
+    /// The parameters to encode pages with.
+    pub fn encoder_config(&self) -> EncoderConfig {
+        EncoderConfig {
+            coding: self.coding,
+            width: (self.width as u32 * self.resolution.x as u32 / 204) as u16,
+            resolution: self.resolution,
+            // with ECM the data is buffered, so there is no need for fill bits
+            bit_rate: self.modem.bit_rate(),
+            min_scan_time: if self.ecm.is_some() { 0 } else { self.scan_time },
+            ecm: self.ecm,
+        }
+    }
+}
+
+/// The `k` parameter of two-dimensional coding: 2 at standard and 4 at higher resolutions.
+pub fn k_factor(resolution: Resolution) -> u16 {
+    if resolution == Resolution::STANDARD { 2 } else { 4 }
+}
+
+/// How pages have to be coded for a session.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct EncoderConfig {
+    pub coding: Coding,
+    /// Width of the lines in pels, at the horizontal resolution.
+    pub width: u16,
+    pub resolution: Resolution,
+    /// Data signalling rate of the modem in bit/s.
+    pub bit_rate: u32,
+    /// Minimum time per coded line in milliseconds, see `encoder::G3Encoder::with_min_scan_time`.
+    pub min_scan_time: u8,
+    /// Frame size if the data is sent in ECM frames.
+    pub ecm: Option<FrameSize>,
+}
+
+#[test]
+fn test_t30() {
+    let dis = Dis {
+        ecm: true,
+        t6: true,
+        superfine: true,
+        max_width: 2048,
+        scan_time: ScanTime { ms: 10, half_at_fine: true },
+        ..Dis::default()
+    };
+    let fif = dis.to_fif();
+    // bits 10, 11, 12, 14, 15 and 16; 17; 20 and 22 and 23
+    assert_eq!(&fif[.. 3], &[0b0000_0000, 0b1110_1110, 0b1110_1001]);
+    assert_eq!(fif.len(), 6);
+    assert_eq!(fif[3], 0b1100_0100);
+    assert_eq!(Dis::parse(&fif), Some(dis));
+    assert_eq!(Dis::parse(&[0, 0x40, 0]).unwrap().modems, Modems::V27terFallback);
+
+    let dcs = Dcs {
+        modem: Modem::V17_14400,
+        resolution: Resolution::FINE,
+        coding: Coding::Group3TwoD { k: 4 },
+        uncompressed: false,
+        ecm: None,
+        width: 1728,
+        length: Length::A4,
+        scan_time: 20,
+    };
+    let fif = dcs.to_fif();
+    assert_eq!(fif, [0, 0b1110_0010, 0]);
+    assert_eq!(Dcs::parse(&fif), Some(dcs.clone()));
+    let config = dcs.encoder_config();
+    assert_eq!((config.coding, config.bit_rate, config.min_scan_time), (Coding::Group3TwoD { k: 4 }, 14400, 20));
+
+    // bit 45 (metric resolution preferred) is only used in DIS
+    let superfine = Dcs { resolution: Resolution::SUPERFINE, ..dcs.clone() };
+    assert!(!bit(&superfine.to_fif(), 45));
+    assert_eq!(Dcs::parse(&superfine.to_fif()), Some(superfine));
+
+    let dcs = Dcs { coding: Coding::Group4, ecm: Some(FrameSize::Octets64), width: 2432, ..dcs };
+    assert_eq!(Dcs::parse(&dcs.to_fif()), Some(dcs.clone()));
+    assert_eq!(dcs.encoder_config().min_scan_time, 0);
+
+    // an ultrafine page sent at fine resolution keeps its width in mm
+    let fine = Dis { fine: true, max_width: 2432, ..Dis::default() };
+    let dcs = Dcs::negotiate(&fine, &Dis { ultrafine: true, superfine: true, ..fine.clone() }, Resolution::ULTRAFINE, 3456).unwrap();
+    assert_eq!((dcs.resolution, dcs.width), (Resolution::FINE, 1728));
+    assert_eq!(dcs.encoder_config().width, 1728);
+}
@@ -0,0 +1,579 @@
+use std::collections::VecDeque;
+use crate::{BitReader, BitWriter, Bits, ByteReader, VecWriter};
+use crate::t30::Modem;
+
+/// Signals of the T.30 indicator packets.
+///
+/// The values after `V17_14400LongTraining` are extensions added in T.38 (2002).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Indicator {
+    NoSignal,
+    Cng,
+    Ced,
+    V21Preamble,
+    V27ter2400Training,
+    V27ter4800Training,
+    V29_7200Training,
+    V29_9600Training,
+    V17_7200ShortTraining,
+    V17_7200LongTraining,
+    V17_9600ShortTraining,
+    V17_9600LongTraining,
+    V17_12000ShortTraining,
+    V17_12000LongTraining,
+    V17_14400ShortTraining,
+    V17_14400LongTraining,
+    V8Ansam,
+    V8Signal,
+    V34CntlChannel1200,
+    V34PriChannel,
+    V34CcRetrain,
+    V33_12000Training,
+    V33_14400Training,
+}
+use Indicator::*;
+const INDICATORS: [Indicator; 23] = [
+    NoSignal, Cng, Ced, V21Preamble, V27ter2400Training, V27ter4800Training, V29_7200Training,
+    V29_9600Training, V17_7200ShortTraining, V17_7200LongTraining, V17_9600ShortTraining,
+    V17_9600LongTraining, V17_12000ShortTraining, V17_12000LongTraining, V17_14400ShortTraining,
+    V17_14400LongTraining, V8Ansam, V8Signal, V34CntlChannel1200, V34PriChannel, V34CcRetrain,
+    V33_12000Training, V33_14400Training,
+];
+
+/// Modulation of the data packets.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DataType {
+    V21,
+    V27ter2400,
+    V27ter4800,
+    V29_7200,
+    V29_9600,
+    V17_7200,
+    V17_9600,
+    V17_12000,
+    V17_14400,
+    V8,
+    V34PriRate,
+    V34Cc1200,
+    V34PriCh,
+    V33_12000,
+    V33_14400,
+}
+const DATA_TYPES: [DataType; 15] = [
+    DataType::V21, DataType::V27ter2400, DataType::V27ter4800, DataType::V29_7200, DataType::V29_9600,
+    DataType::V17_7200, DataType::V17_9600, DataType::V17_12000, DataType::V17_14400, DataType::V8,
+    DataType::V34PriRate, DataType::V34Cc1200, DataType::V34PriCh, DataType::V33_12000, DataType::V33_14400,
+];
+impl From<Modem> for DataType {
+    fn from(modem: Modem) -> Self {
+        match modem {
+            Modem::V27ter2400 => DataType::V27ter2400,
+            Modem::V27ter4800 => DataType::V27ter4800,
+            Modem::V29_7200 => DataType::V29_7200,
+            Modem::V29_9600 => DataType::V29_9600,
+            Modem::V17_7200 => DataType::V17_7200,
+            Modem::V17_9600 => DataType::V17_9600,
+            Modem::V17_12000 => DataType::V17_12000,
+            Modem::V17_14400 => DataType::V17_14400,
+        }
+    }
+}
+
+/// Type of a field in a data packet.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FieldType {
+    HdlcData,
+    HdlcSigEnd,
+    HdlcFcsOk,
+    HdlcFcsBad,
+    HdlcFcsOkSigEnd,
+    HdlcFcsBadSigEnd,
+    T4NonEcmData,
+    T4NonEcmSigEnd,
+    CmMessage,
+    JmMessage,
+    CiMessage,
+    V34Rate,
+}
+const FIELD_TYPES: [FieldType; 12] = [
+    FieldType::HdlcData, FieldType::HdlcSigEnd, FieldType::HdlcFcsOk, FieldType::HdlcFcsBad,
+    FieldType::HdlcFcsOkSigEnd, FieldType::HdlcFcsBadSigEnd, FieldType::T4NonEcmData,
+    FieldType::T4NonEcmSigEnd, FieldType::CmMessage, FieldType::JmMessage, FieldType::CiMessage,
+    FieldType::V34Rate,
+];
+
+/// A field of a data packet. Empty `data` means the field data is absent.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Field {
+    pub field_type: FieldType,
+    pub data: Vec<u8>,
+}
+
+/// An IFP packet.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Ifp {
+    Indicator(Indicator),
+    Data(DataType, Vec<Field>),
+}
+
+fn write_bits(writer: &mut VecWriter, data: u16, len: u8) {
+    writer.write(Bits { data, len });
+}
+fn read_bits(reader: &mut impl BitReader, len: u8) -> Option<u16> {
+    if len > 8 {
+        // `peek` can not return all 16 bits
+        let high = read_bits(reader, len - 8)?;
+        return Some(high << 8 | read_bits(reader, 8)?);
+    }
+    let bits = reader.peek(len)?;
+    reader.consume(len);
+    Some(bits)
+}
+fn align(reader: &mut impl BitReader) {
+    let bits = reader.bits_to_byte_boundary();
+    reader.consume(bits);
+}
+
+/// An enumeration with extension marker, of which the first `root` values use `bits` bits.
+fn write_enum(writer: &mut VecWriter, index: usize, root: usize, bits: u8) {
+    if index < root {
+        write_bits(writer, 0, 1);
+        write_bits(writer, index as u16, bits);
+    } else {
+        // extension bit, then a normally small number
+        write_bits(writer, 0b10, 2);
+        write_bits(writer, (index - root) as u16, 6);
+    }
+}
+fn read_enum(reader: &mut impl BitReader, root: usize, bits: u8) -> Option<usize> {
+    if read_bits(reader, 1)? == 0 {
+        Some(read_bits(reader, bits)? as usize)
+    } else if read_bits(reader, 1)? == 0 {
+        Some(root + read_bits(reader, 6)? as usize)
+    } else {
+        None
+    }
+}
+
+/// Write a length determinant, which starts at a byte boundary. Lengths are below 16384.
+fn write_length(writer: &mut VecWriter, len: usize) {
+    assert!(len < 0x4000);
+    writer.pad();
+    if len < 0x80 {
+        write_bits(writer, len as u16, 8);
+    } else {
+        write_bits(writer, 0x8000 | len as u16, 16);
+    }
+}
+fn read_length(reader: &mut impl BitReader) -> Option<usize> {
+    align(reader);
+    match read_bits(reader, 8)? {
+        len if len < 0x80 => Some(len as usize),
+        len if len < 0xC0 => Some(((len & 0x3F) << 8 | read_bits(reader, 8)?) as usize),
+        // fragmented
+        _ => None,
+    }
+}
+fn write_octets(writer: &mut VecWriter, data: &[u8]) {
+    for &b in data {
+        write_bits(writer, b as u16, 8);
+    }
+}
+fn read_octets(reader: &mut impl BitReader, len: usize) -> Option<Vec<u8>> {
+    (0 .. len).map(|_| read_bits(reader, 8).map(|b| b as u8)).collect()
+}
+
+impl Ifp {
+    /// Encode the packet as in T.38 (2002) with the aligned variant of PER.
+    ///
+    /// Field data is limited to 65535 octets.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = VecWriter::new();
+        match self {
+            Ifp::Indicator(indicator) => {
+                write_bits(&mut writer, 0b00, 2);
+                write_enum(&mut writer, *indicator as usize, 16, 4);
+            }
+            Ifp::Data(data_type, fields) => {
+                write_bits(&mut writer, if fields.is_empty() { 0b01 } else { 0b11 }, 2);
+                write_enum(&mut writer, *data_type as usize, 9, 4);
+                if !fields.is_empty() {
+                    write_length(&mut writer, fields.len());
+                }
+                for field in fields {
+                    write_bits(&mut writer, !field.data.is_empty() as u16, 1);
+                    write_enum(&mut writer, field.field_type as usize, 8, 3);
+                    if !field.data.is_empty() {
+                        assert!(field.data.len() <= 0xFFFF);
+                        writer.pad();
+                        write_bits(&mut writer, (field.data.len() - 1) as u16, 16);
+                        write_octets(&mut writer, &field.data);
+                    }
+                }
+            }
+        }
+        writer.finish()
+    }
+    /// Decode a packet. Anything after the packet (such as the padding of packets
+    /// recovered with FEC) is ignored.
+    pub fn parse(data: &[u8]) -> Option<Ifp> {
+        let mut reader = ByteReader::from_slice(data);
+        let present = read_bits(&mut reader, 1)? == 1;
+        if read_bits(&mut reader, 1)? == 0 {
+            let indicator = *INDICATORS.get(read_enum(&mut reader, 16, 4)?)?;
+            return Some(Ifp::Indicator(indicator));
+        }
+        let data_type = *DATA_TYPES.get(read_enum(&mut reader, 9, 4)?)?;
+        let mut fields = vec![];
+        if present {
+            for _ in 0 .. read_length(&mut reader)? {
+                let has_data = read_bits(&mut reader, 1)? == 1;
+                let field_type = *FIELD_TYPES.get(read_enum(&mut reader, 8, 3)?)?;
+                let data = if has_data {
+                    align(&mut reader);
+                    let len = read_bits(&mut reader, 16)? as usize + 1;
+                    read_octets(&mut reader, len)?
+                } else {
+                    vec![]
+                };
+                fields.push(Field { field_type, data });
+            }
+        }
+        Some(Ifp::Data(data_type, fields))
+    }
+}
+
+/// Packets for HDLC frames (without FCS), such as the T.30 control frames at V.21.
+///
+/// Each frame is followed by `hdlc-fcs-OK`, the last one ends the signal.
+pub fn frames_to_ifp<F: AsRef<[u8]>>(frames: &[F], data_type: DataType) -> Vec<Ifp> {
+    frames.iter().enumerate().map(|(i, frame)| {
+        let end = if i + 1 == frames.len() { FieldType::HdlcFcsOkSigEnd } else { FieldType::HdlcFcsOk };
+        Ifp::Data(data_type, vec![
+            Field { field_type: FieldType::HdlcData, data: frame.as_ref().to_vec() },
+            Field { field_type: end, data: vec![] },
+        ])
+    }).collect()
+}
+
+/// Packets for non-ECM data (a page or TCF), in chunks of up to `chunk` octets,
+/// followed by `t4-non-ecm-sig-end`.
+pub fn data_to_ifp(data: &[u8], data_type: DataType, chunk: usize) -> Vec<Ifp> {
+    let mut packets: Vec<Ifp> = data.chunks(chunk).map(|c| {
+        Ifp::Data(data_type, vec![Field { field_type: FieldType::T4NonEcmData, data: c.to_vec() }])
+    }).collect();
+    packets.push(Ifp::Data(data_type, vec![Field { field_type: FieldType::T4NonEcmSigEnd, data: vec![] }]));
+    packets
+}
+
+/// What `IfpReceiver` collected from the packets.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Received {
+    Indicator(Indicator),
+    /// An HDLC frame with a good FCS.
+    Frame(Vec<u8>),
+    /// An HDLC frame the gateway received with a bad FCS.
+    BadFrame,
+    /// The complete non-ECM data of a signal, either TCF or a page for `decoder::decode_g3`.
+    Data(Vec<u8>),
+}
+
+/// Joins the fields of received packets into frames and page data.
+#[derive(Default)]
+pub struct IfpReceiver {
+    frame: Vec<u8>,
+    data: Vec<u8>,
+}
+impl IfpReceiver {
+    pub fn new() -> Self {
+        IfpReceiver::default()
+    }
+    pub fn receive(&mut self, ifp: &Ifp) -> Vec<Received> {
+        let fields = match ifp {
+            Ifp::Indicator(indicator) => return vec![Received::Indicator(*indicator)],
+            Ifp::Data(_, fields) => fields,
+        };
+        let mut received = vec![];
+        for field in fields {
+            match field.field_type {
+                FieldType::HdlcData => self.frame.extend_from_slice(&field.data),
+                FieldType::HdlcFcsOk | FieldType::HdlcFcsOkSigEnd => {
+                    self.frame.extend_from_slice(&field.data);
+                    received.push(Received::Frame(std::mem::take(&mut self.frame)));
+                }
+                FieldType::HdlcFcsBad | FieldType::HdlcFcsBadSigEnd => {
+                    self.frame.clear();
+                    received.push(Received::BadFrame);
+                }
+                // the signal ended within a frame
+                FieldType::HdlcSigEnd => self.frame.clear(),
+                FieldType::T4NonEcmData => self.data.extend_from_slice(&field.data),
+                FieldType::T4NonEcmSigEnd => {
+                    self.data.extend_from_slice(&field.data);
+                    received.push(Received::Data(std::mem::take(&mut self.data)));
+                }
+                _ => {}
+            }
+        }
+        received
+    }
+}
+
+/// How UDPTL packets protect against packet loss.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Recovery {
+    /// Repeat the given number of previous IFP packets in each packet.
+    Redundancy(usize),
+    /// Send `entries` XORs of previous packets. Entry `m` covers the `span` packets
+    /// `seq + m - entries * k` for `k` in `1 ..= span`.
+    Fec { span: u8, entries: u8 },
+}
+
+/// Sequence numbers of the packets covered by FEC entry `m` of packet `seq`.
+fn fec_packets(seq: u16, m: u8, span: u8, entries: u8) -> impl Iterator<Item=u16> {
+    let first = seq as i32 + m as i32;
+    (1 ..= span as i32).map(move |k| first - entries as i32 * k).filter(|&n| n >= 0).map(|n| n as u16)
+}
+
+fn xor_into(acc: &mut Vec<u8>, data: &[u8]) {
+    if acc.len() < data.len() {
+        acc.resize(data.len(), 0);
+    }
+    for (a, &b) in acc.iter_mut().zip(data) {
+        *a ^= b;
+    }
+}
+
+/// Number of IFP packets to remember for recovery.
+const HISTORY: usize = 256;
+
+/// Wraps IFP packets into UDPTL packets.
+pub struct UdptlEncoder {
+    recovery: Recovery,
+    seq: u16,
+    /// the last packets, most recent first
+    history: VecDeque<Vec<u8>>,
+}
+impl UdptlEncoder {
+    pub fn new(recovery: Recovery) -> Self {
+        UdptlEncoder { recovery, seq: 0, history: VecDeque::new() }
+    }
+    /// The UDPTL packet for the encoded IFP packet `ifp`.
+    pub fn packet(&mut self, ifp: &[u8]) -> Vec<u8> {
+        let mut writer = VecWriter::new();
+        write_bits(&mut writer, self.seq, 16);
+        write_length(&mut writer, ifp.len());
+        write_octets(&mut writer, ifp);
+        match self.recovery {
+            Recovery::Redundancy(n) => {
+                let n = n.min(self.history.len());
+                write_bits(&mut writer, 0, 1);
+                write_length(&mut writer, n);
+                for packet in self.history.iter().take(n) {
+                    write_length(&mut writer, packet.len());
+                    write_octets(&mut writer, packet);
+                }
+            }
+            Recovery::Fec { span, entries } => {
+                write_bits(&mut writer, 1, 1);
+                // fec-npackets, an unconstrained integer
+                write_length(&mut writer, 1);
+                write_bits(&mut writer, span as u16, 8);
+                write_length(&mut writer, entries as usize);
+                for m in 0 .. entries {
+                    let mut entry = vec![];
+                    for n in fec_packets(self.seq, m, span, entries) {
+                        if let Some(packet) = self.history.get((self.seq - n - 1) as usize) {
+                            xor_into(&mut entry, packet);
+                        }
+                    }
+                    write_length(&mut writer, entry.len());
+                    write_octets(&mut writer, &entry);
+                }
+            }
+        }
+        self.history.push_front(ifp.to_vec());
+        self.history.truncate(HISTORY);
+        self.seq = self.seq.wrapping_add(1);
+        writer.finish()
+    }
+}
+
+enum Secondary {
+    Redundancy(Vec<Vec<u8>>),
+    Fec { span: u8, entries: Vec<Vec<u8>> },
+}
+
+/// Unwraps UDPTL packets, recovering lost IFP packets where possible.
+#[derive(Default)]
+pub struct UdptlDecoder {
+    /// the sequence number expected next
+    next: Option<u16>,
+    /// received and recovered packets, most recent last
+    history: VecDeque<(u16, Vec<u8>)>,
+    lost: u32,
+}
+impl UdptlDecoder {
+    pub fn new() -> Self {
+        UdptlDecoder::default()
+    }
+    fn known(&self, seq: u16) -> Option<&[u8]> {
+        self.history.iter().rev().find(|(n, _)| *n == seq).map(|(_, p)| p.as_slice())
+    }
+    /// Handle a received UDPTL packet.
+    ///
+    /// Returns the IFP packets that are now available, in order: those recovered for the
+    /// packets that were lost since the last call, then the primary one. Packets that
+    /// arrive late are dropped. Returns `None` if the packet is malformed.
+    pub fn receive(&mut self, packet: &[u8]) -> Option<Vec<Vec<u8>>> {
+        let mut reader = ByteReader::from_slice(packet);
+        let seq = read_bits(&mut reader, 16)?;
+        let len = read_length(&mut reader)?;
+        let primary = read_octets(&mut reader, len)?;
+        let secondary = if read_bits(&mut reader, 1)? == 0 {
+            let packets = (0 .. read_length(&mut reader)?)
+                .map(|_| read_length(&mut reader).and_then(|len| read_octets(&mut reader, len)))
+                .collect::<Option<Vec<_>>>()?;
+            Secondary::Redundancy(packets)
+        } else {
+            if read_length(&mut reader)? != 1 {
+                return None;
+            }
+            let span = read_bits(&mut reader, 8)? as u8;
+            let entries = (0 .. read_length(&mut reader)?)
+                .map(|_| read_length(&mut reader).and_then(|len| read_octets(&mut reader, len)))
+                .collect::<Option<Vec<_>>>()?;
+            Secondary::Fec { span, entries }
+        };
+
+        let next = self.next.unwrap_or(seq);
+        let gap = seq.wrapping_sub(next);
+        if gap >= 0x8000 {
+            return Some(vec![]);
+        }
+        let mut recovered: Vec<Option<Vec<u8>>> = vec![None; gap as usize];
+        match secondary {
+            Secondary::Redundancy(packets) => {
+                for (i, packet) in packets.into_iter().enumerate().take(gap as usize) {
+                    recovered[gap as usize - 1 - i] = Some(packet);
+                }
+            }
+            Secondary::Fec { span, entries } => {
+                let count = entries.len() as u8;
+                for (m, entry) in entries.into_iter().enumerate() {
+                    let covered: Vec<u16> = fec_packets(seq, m as u8, span, count).collect();
+                    let unknown: Vec<u16> = covered.iter().cloned().filter(|&n| self.known(n).is_none()).collect();
+                    if let [n] = unknown[..] {
+                        let i = n.wrapping_sub(next) as usize;
+                        if i >= recovered.len() {
+                            continue;
+                        }
+                        let mut packet = entry;
+                        for &other in covered.iter().filter(|&&o| o != n) {
+                            xor_into(&mut packet, self.known(other).unwrap());
+                        }
+                        recovered[i] = Some(packet);
+                    }
+                }
+            }
+        }
+
+        let mut packets = vec![];
+        for (i, packet) in recovered.into_iter().enumerate() {
+            match packet {
+                Some(packet) => {
+                    self.history.push_back((next.wrapping_add(i as u16), packet.clone()));
+                    packets.push(packet);
+                }
+                None => self.lost += 1,
+            }
+        }
+        self.history.push_back((seq, primary.clone()));
+        packets.push(primary);
+        while self.history.len() > HISTORY {
+            self.history.pop_front();
+        }
+        self.next = Some(seq.wrapping_add(1));
+        Some(packets)
+    }
+    /// Number of IFP packets that were lost and could not be recovered.
+    pub fn lost(&self) -> u32 {
+        self.lost
+    }
+}
+
+#[test]
+fn test_t38() {
+    use std::net::UdpSocket;
+    use std::time::Duration;
+    use crate::encoder::G3Encoder;
+    use crate::decoder::decode_g3_checked;
+
+    assert_eq!(Ifp::Indicator(Ced).to_bytes(), [0x04]);
+    let frame = [0xFF, 0x13, 0x80, 0x00, 0xCE];
+    let packet = frames_to_ifp(&[frame], DataType::V21).remove(0);
+    assert_eq!(packet.to_bytes(), [0xC0, 0x02, 0x80, 0x00, 0x04, 0xFF, 0x13, 0x80, 0x00, 0xCE, 0x20]);
+    let extended = [
+        Ifp::Indicator(V33_14400Training),
+        Ifp::Data(DataType::V33_12000, vec![]),
+        Ifp::Data(DataType::V34PriRate, vec![
+            Field { field_type: FieldType::V34Rate, data: vec![1, 2, 3] },
+            Field { field_type: FieldType::CiMessage, data: vec![] },
+            Field { field_type: FieldType::HdlcSigEnd, data: vec![] },
+        ]),
+    ];
+    for packet in &extended {
+        let mut bytes = packet.to_bytes();
+        bytes.extend_from_slice(&[0, 0]);
+        assert_eq!(Ifp::parse(&bytes).as_ref(), Some(packet));
+    }
+
+    let lines = crate::test_lines(300, 1728);
+    let mut encoder = G3Encoder::new(VecWriter::new());
+    for line in &lines {
+        encoder.encode_transitions(line, 1728);
+    }
+    let page = encoder.finish().finish();
+    let mut packets = vec![Ifp::Indicator(V21Preamble)];
+    packets.extend(frames_to_ifp(&[&frame[..], &[0xFF, 0x13, 0x84]], DataType::V21));
+    packets.push(Ifp::Indicator(V17_14400LongTraining));
+    packets.extend(data_to_ifp(&page, Modem::V17_14400.into(), 100));
+
+    let recoveries = [Recovery::Redundancy(2), Recovery::Fec { span: 2, entries: 3 }];
+    for recovery in &recoveries {
+        let tx = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let rx = UdpSocket::bind("127.0.0.1:0").unwrap();
+        rx.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut encoder = UdptlEncoder::new(*recovery);
+        for (seq, packet) in packets.iter().enumerate() {
+            let data = encoder.packet(&packet.to_bytes());
+            // lose one or two packets in a row now and then
+            if ![3, 10, 11, 20].contains(&seq) {
+                tx.send_to(&data, rx.local_addr().unwrap()).unwrap();
+            }
+        }
+
+        let mut decoder = UdptlDecoder::new();
+        let mut receiver = IfpReceiver::new();
+        let mut received = vec![];
+        let mut buf = [0; 2048];
+        while received.last().is_none_or(|r| !matches!(r, Received::Data(_))) {
+            let len = rx.recv(&mut buf).unwrap();
+            for ifp in decoder.receive(&buf[.. len]).unwrap() {
+                received.extend(receiver.receive(&Ifp::parse(&ifp).unwrap()));
+            }
+        }
+        assert_eq!(decoder.lost(), 0);
+        assert_eq!(received[1 .. 3], [Received::Frame(frame.to_vec()), Received::Frame(vec![0xFF, 0x13, 0x84])]);
+        assert_eq!(received[3], Received::Indicator(V17_14400LongTraining));
+        let data = match &received[4] {
+            Received::Data(data) => data,
+            _ => panic!(),
+        };
+        assert_eq!(data, &page);
+        let mut decoded = vec![];
+        let stats = decode_g3_checked(data.iter().cloned(), 1728, false, |line| decoded.push(line.to_vec()));
+        assert_eq!(stats.bad_lines, 0);
+        assert_eq!(decoded, lines);
+    }
+}
@@ -1,12 +1,212 @@
+use std::convert::TryFrom;
+use crate::{Coding, Resolution, VecWriter};
+use crate::decoder::{self, LineStats, pels};
+use crate::encoder::Encoder;
+
 #[derive(Copy, Clone)]
 enum Value {
     Short(u16),
+    ShortPair(u16, u16),
     Long(u32),
     Rational(u32, u32),
     DataOffset,
+    DataLength,
+}
+
+/// Byte order of a TIFF file.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ByteOrder {
+    /// "II", least significant byte first.
+    LittleEndian,
+    /// "MM", most significant byte first.
+    BigEndian,
+}
+
+/// Options for writing TIFF files.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Options {
+    pub byte_order: ByteOrder,
+    /// Write a BigTIFF (version 43) file with 64 bit offsets.
+    pub big_tiff: bool,
+}
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            byte_order: ByteOrder::LittleEndian,
+            big_tiff: false,
+        }
+    }
+}
+
+struct Out {
+    buf: Vec<u8>,
+    options: Options,
+}
+impl Out {
+    fn u16(&mut self, n: u16) {
+        match self.options.byte_order {
+            ByteOrder::LittleEndian => self.buf.extend_from_slice(&n.to_le_bytes()),
+            ByteOrder::BigEndian => self.buf.extend_from_slice(&n.to_be_bytes()),
+        }
+    }
+    fn u32(&mut self, n: u32) {
+        match self.options.byte_order {
+            ByteOrder::LittleEndian => self.buf.extend_from_slice(&n.to_le_bytes()),
+            ByteOrder::BigEndian => self.buf.extend_from_slice(&n.to_be_bytes()),
+        }
+    }
+    fn u64(&mut self, n: u64) {
+        match self.options.byte_order {
+            ByteOrder::LittleEndian => self.buf.extend_from_slice(&n.to_le_bytes()),
+            ByteOrder::BigEndian => self.buf.extend_from_slice(&n.to_be_bytes()),
+        }
+    }
+    /// write an offset or count (4 bytes in TIFF, 8 bytes in BigTIFF)
+    fn offset(&mut self, n: u64) {
+        if self.options.big_tiff {
+            self.u64(n);
+        } else {
+            assert!(n <= u32::MAX as u64, "data too large for classic TIFF, use BigTIFF");
+            self.u32(n as u32);
+        }
+    }
+    /// pad the value field of an IFD entry after `used` bytes
+    fn pad_value(&mut self, used: usize) {
+        let size = if self.options.big_tiff { 8 } else { 4 };
+        self.buf.extend(std::iter::repeat_n(0, size - used));
+    }
+    /// overwrite the offset at `pos`
+    fn patch_offset(&mut self, pos: usize, n: u64) {
+        let mut tmp = Out { buf: Vec::with_capacity(8), options: self.options };
+        tmp.offset(n);
+        self.buf[pos .. pos + tmp.buf.len()].copy_from_slice(&tmp.buf);
+    }
+}
+
+/// Encode a value, returning the field type, the count and the bytes.
+///
+/// `data_offset` is the position of the first strip in the file.
+fn encode_value(val: Value, strips: &[&[u8]], data_offset: u64, options: Options) -> (u16, u64, Vec<u8>) {
+    let mut out = Out { buf: Vec::with_capacity(8), options };
+    let offset_type = if options.big_tiff { 16 } else { 4 }; // LONG8 or LONG
+    let (typ, count) = match val {
+        Value::Short(n) => {
+            out.u16(n);
+            (3, 1)
+        }
+        Value::ShortPair(a, b) => {
+            out.u16(a);
+            out.u16(b);
+            (3, 2)
+        }
+        Value::Long(n) => {
+            out.u32(n);
+            (4, 1)
+        }
+        Value::Rational(num, denom) => {
+            out.u32(num);
+            out.u32(denom);
+            (5, 1)
+        }
+        Value::DataOffset => {
+            let mut pos = data_offset;
+            for strip in strips {
+                out.offset(pos);
+                pos += strip.len() as u64;
+            }
+            (offset_type, strips.len())
+        }
+        Value::DataLength => {
+            for strip in strips {
+                out.offset(strip.len() as u64);
+            }
+            (offset_type, strips.len())
+        }
+    };
+    (typ, count as u64, out.buf)
 }
 
+type Page<'a> = (&'a [(u16, Value)], &'a [&'a [u8]]);
+
+/// Write `pages` (list of IFD entries and the strips) into a TIFF file.
+///
+/// The entries have to be sorted by tag.
+fn write_pages(pages: &[Page], options: Options) -> Vec<u8> {
+    let big = options.big_tiff;
+    let (entry_size, count_size, offset_size) = if big { (20, 8, 8) } else { (12, 2, 4) };
+
+    let mut out = Out { buf: Vec::new(), options };
+    match options.byte_order {
+        ByteOrder::LittleEndian => out.buf.extend_from_slice(b"II"),
+        ByteOrder::BigEndian => out.buf.extend_from_slice(b"MM"),
+    }
+    if big {
+        out.u16(43);
+        out.u16(8); // bytesize of offsets
+        out.u16(0);
+    } else {
+        out.u16(42);
+    }
+    let mut next_ifd_pos = out.buf.len();
+    out.offset(0);
+
+    for &(entries, strips) in pages {
+        // IFDs have to start on a word boundary
+        if out.buf.len() % 2 == 1 {
+            out.buf.push(0);
+        }
+        let ifd_start = out.buf.len() as u64;
+        out.patch_offset(next_ifd_pos, ifd_start);
+
+        // values that do not fit into the value field are stored after the IFD
+        let extra_len: usize = entries.iter()
+            .map(|&(_, val)| encode_value(val, strips, 0, options).2.len())
+            .filter(|&len| len > offset_size)
+            .sum();
+        let ifd_end = ifd_start + (count_size + entry_size * entries.len() + offset_size) as u64;
+        let data_offset = ifd_end + extra_len as u64;
+
+        if big {
+            out.u64(entries.len() as u64);
+        } else {
+            out.u16(entries.len() as u16);
+        }
+
+        let mut extra = vec![];
+        for &(tag, val) in entries {
+            let (typ, count, bytes) = encode_value(val, strips, data_offset, options);
+            out.u16(tag);
+            out.u16(typ);
+            out.offset(count);
+            if bytes.len() <= offset_size {
+                out.buf.extend_from_slice(&bytes);
+                out.pad_value(bytes.len());
+            } else {
+                out.offset(ifd_end + extra.len() as u64);
+                extra.extend_from_slice(&bytes);
+            }
+        }
+        next_ifd_pos = out.buf.len();
+        out.offset(0);
+
+        // write additional data
+        out.buf.extend_from_slice(&extra);
+
+        assert_eq!(out.buf.len() as u64, data_offset);
+        for strip in strips {
+            out.buf.extend_from_slice(strip);
+        }
+    }
+    out.buf
+}
+
+/// Wrap Group 4 encoded data in a little-endian TIFF file.
 pub fn wrap(data: &[u8], width: u32, height: u32) -> Vec<u8> {
+    wrap_with(data, width, height, Options::default())
+}
+
+/// Like `wrap`, but with control over byte order and BigTIFF output.
+pub fn wrap_with(data: &[u8], width: u32, height: u32, options: Options) -> Vec<u8> {
     use Value::*;
     let header_data = [
         (256, Long(width)), // ImageWidth
@@ -16,58 +216,418 @@ pub fn wrap(data: &[u8], width: u32, height: u32) -> Vec<u8> {
         (273, DataOffset), // StripOffsets
         (274, Short(1)), // Orientation
         (278, Long(height)), // RowsPerStrip
-        (279, Long(data.len() as u32)), // StripByteCounts
+        (279, DataLength), // StripByteCounts
         (282, Rational(200, 1)), // XResolution
         (283, Rational(200, 1)), // YResolution
         (296, Short(2)), // ResolutionUnit
     ];
-    let rat_data_len = 2 * 8; // number of rationals * 8
-    let ifd_end = 
-        4 + // magic
-        4 + // IFD offset
-        2 + // IFD entry count
-        12 * header_data.len() + // IFD enties
-        4; // null pointer at end of IFD
-    let header_size = ifd_end + rat_data_len;
-    
-    let mut out = Vec::with_capacity(header_size + data.len());
-
-    out.extend_from_slice(&[73, 73, 42, 0]);
-    let ifd_offset: u32 = 8;
-    out.extend_from_slice(&ifd_offset.to_le_bytes());
-
-    out.extend_from_slice(&u16::to_le_bytes(header_data.len() as u16));
-
-    let mut num_rat = 0;
-    for &(tag, val) in header_data.iter() {
-        let (typ_num, val) = match val {
-            Short(n) => (3, n as u32),
-            Long(n) => (4, n),
-            Rational(_, _) => {
-                let o = ifd_end + 8 * num_rat;
-                num_rat += 1;
-                (5, o as u32)
+    write_pages(&[(&header_data, &[data])], options)
+}
+
+/// Reasons for pages not to conform to TIFF Class F.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ClassFError {
+    /// A Class F file needs at least one page.
+    NoPages,
+    /// The resolution of page `page` (counting from 0) is not a fax resolution.
+    Resolution { page: usize, resolution: Resolution },
+    /// The width of page `page` is not a fax page width at its resolution.
+    Width { page: usize, width: u16 },
+}
+
+/// One page of a TIFF Class F (fax) file.
+#[derive(Clone, Debug)]
+pub struct FaxPage {
+    /// The coded image data. Group 3 data has to start with an EOL.
+    pub data: Vec<u8>,
+    pub coding: Coding,
+    pub width: u16,
+    pub height: u32,
+    pub resolution: Resolution,
+    /// Damaged lines found while receiving the page.
+    pub stats: LineStats,
+    /// Whether damaged lines were replaced when the page was re-encoded.
+    pub regenerated: bool,
+}
+impl FaxPage {
+    /// Decode a received Group 3 page and re-encode it as Group 4.
+    ///
+    /// Damaged lines are replaced by the previous line and recorded in `stats`.
+    pub fn from_received_g3(input: &[u8], width: u16, two_d: bool, resolution: Resolution) -> FaxPage {
+        let mut encoder = Encoder::new(VecWriter::new());
+        let mut height = 0;
+        let stats = decoder::decode_g3_checked(input.iter().cloned(), width, two_d, |transitions| {
+            encoder.encode_line(pels(transitions, width), width);
+            height += 1;
+        });
+        FaxPage {
+            data: encoder.finish().finish(),
+            coding: Coding::Group4,
+            width,
+            height,
+            resolution,
+            stats,
+            regenerated: true,
+        }
+    }
+
+    fn validate(&self, page: usize) -> Result<(), ClassFError> {
+        let widths = match self.resolution {
+            Resolution { x: 204, y: 98 } | Resolution { x: 204, y: 196 } | Resolution { x: 204, y: 391 } => [1728, 2048, 2432],
+            Resolution { x: 408, y: 391 } => [3456, 4096, 4864],
+            resolution => return Err(ClassFError::Resolution { page, resolution }),
+        };
+        if !widths.contains(&self.width) {
+            return Err(ClassFError::Width { page, width: self.width });
+        }
+        Ok(())
+    }
+}
+
+/// Write a TIFF Class F file (RFC 2306), one IFD per page.
+///
+/// Fails if one of the pages does not use a fax resolution and page width.
+pub fn wrap_class_f(pages: &[FaxPage], options: Options) -> Result<Vec<u8>, ClassFError> {
+    use Value::*;
+    if pages.is_empty() {
+        return Err(ClassFError::NoPages);
+    }
+    for (i, page) in pages.iter().enumerate() {
+        page.validate(i)?;
+    }
+
+    let entries: Vec<Vec<(u16, Value)>> = pages.iter().enumerate().map(|(i, page)| {
+        let (compression, options_tag, coding_options) = match page.coding {
+            Coding::Group3OneD => (3, 292, 0),
+            Coding::Group3TwoD { .. } => (3, 292, 1),
+            Coding::Group4 => (4, 293, 0),
+        };
+        let clean = match (page.stats.bad_lines, page.regenerated) {
+            (0, _) => 0, // clean
+            (_, true) => 1, // regenerated
+            (_, false) => 2, // unclean
+        };
+        vec![
+            (254, Long(2)), // NewSubfileType: page
+            (256, Long(page.width as u32)), // ImageWidth
+            (257, Long(page.height)), // ImageLength
+            (258, Short(1)), // BitsPerSample
+            (259, Short(compression)), // Compression
+            (262, Short(0)), // PhotometricInterpretation
+            (266, Short(1)), // FillOrder
+            (273, DataOffset), // StripOffsets
+            (277, Short(1)), // SamplesPerPixel
+            (278, Long(page.height)), // RowsPerStrip
+            (279, DataLength), // StripByteCounts
+            (282, Rational(page.resolution.x as u32, 1)), // XResolution
+            (283, Rational(page.resolution.y as u32, 1)), // YResolution
+            (options_tag, Long(coding_options)), // T4Options / T6Options
+            (296, Short(2)), // ResolutionUnit
+            (297, ShortPair(i as u16, pages.len() as u16)), // PageNumber
+            (326, Long(page.stats.bad_lines)), // BadFaxLines
+            (327, Short(clean)), // CleanFaxData
+            (328, Long(page.stats.consecutive_bad_lines)), // ConsecutiveBadFaxLines
+        ]
+    }).collect();
+    let strips: Vec<[&[u8]; 1]> = pages.iter().map(|p| [&p.data[..]]).collect();
+    let ifds: Vec<Page> = entries.iter().zip(&strips).map(|(e, s)| (&e[..], &s[..])).collect();
+    Ok(write_pages(&ifds, options))
+}
+
+/// Photometric interpretation of a bi-level TIFF image.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Photometric {
+    /// Decoded 0 bits (white runs) are white. This is the usual case for fax images.
+    WhiteIsZero,
+    /// Decoded 0 bits (white runs) are displayed black.
+    BlackIsZero,
+}
+
+/// A CCITT coded image in a TIFF file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub coding: Coding,
+    pub photometric: Photometric,
+    pub resolution: Option<Resolution>,
+    pub rows_per_strip: u32,
+    /// The coded strips, with the bits of each byte in FillOrder 1 (most significant bit first).
+    pub strips: Vec<Vec<u8>>,
+}
+impl Image {
+    /// Decode the image.
+    ///
+    /// The callback `line_cb` is called for each line, as in `decoder::decode_g4`.
+    /// Damaged Group 3 lines are replaced as in `decoder::decode_g3_checked`.
+    pub fn decode(&self, mut line_cb: impl FnMut(&[u16])) -> Option<()> {
+        let width = u16::try_from(self.width).ok()?;
+        let mut remaining = self.height;
+        for strip in &self.strips {
+            let rows = remaining.min(self.rows_per_strip);
+            let mut left = rows;
+            let mut cb = |line: &[u16]| {
+                if left > 0 {
+                    left -= 1;
+                    line_cb(line);
+                }
+            };
+            match self.coding {
+                Coding::Group3OneD => { decoder::decode_g3_checked(strip.iter().cloned(), width, false, &mut cb); }
+                Coding::Group3TwoD { .. } => { decoder::decode_g3_checked(strip.iter().cloned(), width, true, &mut cb); }
+                Coding::Group4 => decoder::decode_g4(strip.iter().cloned(), width, Some(rows.min(u16::MAX as u32) as u16), &mut cb)?,
+            }
+            // fill missing lines with white
+            for _ in 0 .. left {
+                line_cb(&[]);
+            }
+            remaining -= rows;
+        }
+        Some(())
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+    big_tiff: bool,
+}
+impl<'a> Reader<'a> {
+    fn bytes<const N: usize>(&self, pos: u64) -> Option<[u8; N]> {
+        let pos = usize::try_from(pos).ok()?;
+        let mut b = [0; N];
+        b.copy_from_slice(self.data.get(pos .. pos.checked_add(N)?)?);
+        if !self.big_endian {
+            b.reverse();
+        }
+        Some(b)
+    }
+    fn u16(&self, pos: u64) -> Option<u16> {
+        self.bytes(pos).map(u16::from_be_bytes)
+    }
+    fn u32(&self, pos: u64) -> Option<u32> {
+        self.bytes(pos).map(u32::from_be_bytes)
+    }
+    fn u64(&self, pos: u64) -> Option<u64> {
+        self.bytes(pos).map(u64::from_be_bytes)
+    }
+    fn offset(&self, pos: u64) -> Option<u64> {
+        if self.big_tiff {
+            self.u64(pos)
+        } else {
+            self.u32(pos).map(|n| n as u64)
+        }
+    }
+    /// Read the values of the IFD entry at `pos`. Rationals yield numerator and denominator.
+    fn values(&self, pos: u64) -> Option<Vec<u64>> {
+        let typ = self.u16(pos + 2)?;
+        let count = self.offset(pos + 4)?;
+        let size = match typ {
+            1 | 2 | 6 | 7 => 1, // BYTE, ASCII, SBYTE, UNDEFINED
+            3 | 8 => 2, // SHORT, SSHORT
+            4 | 9 => 4, // LONG, SLONG
+            5 | 10 => 8, // RATIONAL, SRATIONAL
+            16 | 17 => 8, // LONG8, SLONG8
+            _ => return Some(vec![]),
+        };
+        let value_pos = if self.big_tiff { pos + 12 } else { pos + 8 };
+        let len = count.checked_mul(size)?;
+        let start = if len <= if self.big_tiff { 8 } else { 4 } {
+            value_pos
+        } else {
+            self.offset(value_pos)?
+        };
+        if start.checked_add(len)? > self.data.len() as u64 {
+            return None;
+        }
+        let mut values = Vec::with_capacity(count as usize);
+        for i in 0 .. count {
+            match typ {
+                1 | 2 | 6 | 7 => values.push(self.data[(start + i) as usize] as u64),
+                3 | 8 => values.push(self.u16(start + 2 * i)? as u64),
+                4 | 9 => values.push(self.u32(start + 4 * i)? as u64),
+                5 | 10 => {
+                    values.push(self.u32(start + 8 * i)? as u64);
+                    values.push(self.u32(start + 8 * i + 4)? as u64);
+                }
+                _ => values.push(self.u64(start + 8 * i)?),
             }
-            DataOffset => (4, header_size as u32)
+        }
+        Some(values)
+    }
+}
+
+/// Read all CCITT coded images of a TIFF (or BigTIFF) file.
+///
+/// Returns `None` if the file is malformed or contains an image that is not
+/// coded with Group 3 or Group 4.
+pub fn read(data: &[u8]) -> Option<Vec<Image>> {
+    use std::collections::HashMap;
+
+    let big_endian = match data.get(.. 2)? {
+        b"II" => false,
+        b"MM" => true,
+        _ => return None,
+    };
+    let mut reader = Reader { data, big_endian, big_tiff: false };
+    reader.big_tiff = match reader.u16(2)? {
+        42 => false,
+        43 => true,
+        _ => return None,
+    };
+    let mut ifd = reader.offset(if reader.big_tiff { 8 } else { 4 })?;
+
+    let mut images = vec![];
+    while ifd != 0 {
+        if images.len() > data.len() / 8 {
+            return None; // IFD loop
+        }
+        let (count, entries_pos, entry_size) = if reader.big_tiff {
+            (reader.u64(ifd)?, ifd + 8, 20)
+        } else {
+            (reader.u16(ifd)? as u64, ifd + 2, 12)
+        };
+        let mut tags = HashMap::new();
+        for i in 0 .. count {
+            let pos = entries_pos + i * entry_size;
+            tags.insert(reader.u16(pos)?, reader.values(pos)?);
+        }
+        ifd = reader.offset(entries_pos + count * entry_size)?;
+
+        let get = |tag: u16| tags.get(&tag).and_then(|v| v.first()).cloned();
+        let width = get(256)? as u32;
+        let height = get(257)? as u32;
+        let coding = match get(259)? {
+            3 if get(292).unwrap_or(0) & 1 == 1 => Coding::Group3TwoD { k: 0 },
+            3 => Coding::Group3OneD,
+            4 => Coding::Group4,
+            _ => return None,
+        };
+        let photometric = match get(262).unwrap_or(0) {
+            1 => Photometric::BlackIsZero,
+            _ => Photometric::WhiteIsZero,
+        };
+        let resolution = match (tags.get(&282), tags.get(&283)) {
+            (Some(x), Some(y)) if x.len() == 2 && y.len() == 2 && x[1] != 0 && y[1] != 0 => {
+                // ResolutionUnit 3 is centimeters
+                let (num, den) = if get(296) == Some(3) { (254, 100) } else { (1, 1) };
+                let dpi = |r: &[u64]| ((r[0] * num + r[1] * den / 2) / (r[1] * den)) as u16;
+                Some(Resolution { x: dpi(x), y: dpi(y) })
+            }
+            _ => None,
+        };
+        let coding = match coding {
+            // K is not stored in the file
+            Coding::Group3TwoD { .. } => Coding::Group3TwoD { k: if resolution.is_some_and(|r| r.y > 150) { 4 } else { 2 } },
+            c => c,
         };
-        let count = 1;
-        out.extend_from_slice(&u16::to_le_bytes(tag));
-        out.extend_from_slice(&u16::to_le_bytes(typ_num));
-        out.extend_from_slice(&u32::to_le_bytes(count));
-        out.extend_from_slice(&u32::to_le_bytes(val));
+        let rows_per_strip = get(278).unwrap_or(height as u64).min(height as u64) as u32;
+        let reverse = get(266) == Some(2);
+        let offsets = tags.get(&273)?;
+        let lengths = tags.get(&279)?;
+        let strips = offsets.iter().zip(lengths.iter()).map(|(&start, &len)| {
+            let start = usize::try_from(start).ok()?;
+            let strip = data.get(start .. start.checked_add(usize::try_from(len).ok()?)?)?;
+            Some(if reverse {
+                strip.iter().map(|b| b.reverse_bits()).collect()
+            } else {
+                strip.to_vec()
+            })
+        }).collect::<Option<Vec<Vec<u8>>>>()?;
+
+        images.push(Image { width, height, coding, photometric, resolution, rows_per_strip, strips });
     }
-    // NULL at IFD end
-    out.extend_from_slice(&[0; 4]);
+    Some(images)
+}
 
-    // write additional data
-    for &(_, val) in header_data.iter() {
-        if let Value::Rational(nom, denom) = val {
-            out.extend_from_slice(&nom.to_le_bytes());
-            out.extend_from_slice(&denom.to_le_bytes());
+/// Write `images` into a (multi-page) TIFF file.
+pub fn write(images: &[Image], options: Options) -> Vec<u8> {
+    use Value::*;
+    let multi_page = images.len() > 1;
+    let entries: Vec<Vec<(u16, Value)>> = images.iter().enumerate().map(|(i, image)| {
+        let mut entries = vec![];
+        if multi_page {
+            entries.push((254, Long(2))); // NewSubfileType: page
+        }
+        let (compression, options_tag, coding_options) = match image.coding {
+            Coding::Group3OneD => (3, 292, 0),
+            Coding::Group3TwoD { .. } => (3, 292, 1),
+            Coding::Group4 => (4, 293, 0),
+        };
+        let photometric = match image.photometric {
+            Photometric::WhiteIsZero => 0,
+            Photometric::BlackIsZero => 1,
+        };
+        entries.extend_from_slice(&[
+            (256, Long(image.width)), // ImageWidth
+            (257, Long(image.height)), // ImageLength
+            (258, Short(1)), // BitsPerSample
+            (259, Short(compression)), // Compression
+            (262, Short(photometric)), // PhotometricInterpretation
+            (273, DataOffset), // StripOffsets
+            (277, Short(1)), // SamplesPerPixel
+            (278, Long(image.rows_per_strip)), // RowsPerStrip
+            (279, DataLength), // StripByteCounts
+        ]);
+        if let Some(res) = image.resolution {
+            entries.push((282, Rational(res.x as u32, 1))); // XResolution
+            entries.push((283, Rational(res.y as u32, 1))); // YResolution
+        }
+        entries.push((options_tag, Long(coding_options))); // T4Options / T6Options
+        if image.resolution.is_some() {
+            entries.push((296, Short(2))); // ResolutionUnit
+        }
+        if multi_page {
+            entries.push((297, ShortPair(i as u16, images.len() as u16))); // PageNumber
         }
+        entries
+    }).collect();
+    let strips: Vec<Vec<&[u8]>> = images.iter().map(|image| image.strips.iter().map(|s| &s[..]).collect()).collect();
+    let ifds: Vec<Page> = entries.iter().zip(&strips).map(|(e, s)| (&e[..], &s[..])).collect();
+    write_pages(&ifds, options)
+}
+
+#[test]
+fn test_wrap_layout() {
+    let data = [1, 2, 3];
+    let classic = wrap(&data, 8, 1);
+    assert_eq!(&classic[..8], &[73, 73, 42, 0, 8, 0, 0, 0]);
+    // header + IFD + two rationals
+    assert_eq!(classic.len(), 8 + 2 + 11 * 12 + 4 + 16 + 3);
+    assert!(classic.ends_with(&data));
+
+    let options = Options { byte_order: ByteOrder::BigEndian, big_tiff: true };
+    let big = wrap_with(&data, 8, 1, options);
+    assert_eq!(&big[..16], &[77, 77, 0, 43, 0, 8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 16]);
+    assert_eq!(big.len(), 16 + 8 + 11 * 20 + 8 + 3);
+    // ImageWidth entry: tag 256, LONG, count 1, value 8
+    assert_eq!(&big[24..36], &[1, 0, 0, 4, 0, 0, 0, 0, 0, 0, 0, 1]);
+    assert_eq!(&big[36..40], &[0, 0, 0, 8]);
+}
+
+#[test]
+fn test_class_f() {
+    use crate::{Bits, BitWriter, Color, maps::EOL};
+
+    // three white lines with one damaged line in the middle
+    let mut writer = VecWriter::new();
+    let mut encoder = Encoder::new(VecWriter::new());
+    writer.write(EOL);
+    for _ in 0 .. 3 {
+        writer.write(Bits { data: 0b010011011, len: 9 }); // white 1728
+        writer.write(Bits { data: 0b00110101, len: 8 }); // white 0
+        writer.write(EOL);
+        encoder.encode_line(std::iter::repeat_n(Color::White, 1728), 1728);
     }
+    let mut data = writer.finish();
+    data[6] ^= 0x10;
+
+    let page = FaxPage::from_received_g3(&data, 1728, false, Resolution::FINE);
+    assert_eq!(page.height, 3);
+    assert_eq!(page.stats, LineStats { lines: 3, bad_lines: 1, consecutive_bad_lines: 1 });
+    assert_eq!(page.data, encoder.finish().finish());
+    assert!(wrap_class_f(std::slice::from_ref(&page), Options::default()).is_ok());
 
-    assert_eq!(out.len(), header_size);
-    out.extend_from_slice(data);
-    out
+    let narrow = FaxPage { width: 1700, ..page.clone() };
+    assert_eq!(wrap_class_f(&[page, narrow], Options::default()), Err(ClassFError::Width { page: 1, width: 1700 }));
 }
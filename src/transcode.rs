@@ -0,0 +1,130 @@
+use std::convert::TryFrom;
+use crate::{Coding, Color, VecWriter};
+use crate::decoder::{decode_g3_checked, decode_g4};
+use crate::encoder::{Encoder, G3Encoder};
+use crate::tiff::{self, Image, Options};
+
+/// An encoder for any of the supported codings, fed with transition lists.
+pub(crate) enum AnyEncoder {
+    G3(G3Encoder<VecWriter>),
+    G4(Encoder<VecWriter>),
+}
+impl AnyEncoder {
+    pub(crate) fn new(coding: Coding) -> Self {
+        match coding {
+            Coding::Group3OneD => AnyEncoder::G3(G3Encoder::new(VecWriter::new())),
+            Coding::Group3TwoD { k } => AnyEncoder::G3(G3Encoder::new_2d(VecWriter::new(), k)),
+            Coding::Group4 => AnyEncoder::G4(Encoder::new(VecWriter::new())),
+        }
+    }
+    /// Pad the coded lines to the minimum scan time. Group 4 data has no fill bits.
+    pub(crate) fn with_min_scan_time(self, bit_rate: u32, ms: u32) -> Self {
+        match self {
+            AnyEncoder::G3(e) => AnyEncoder::G3(e.with_min_scan_time(bit_rate, ms)),
+            AnyEncoder::G4(e) => AnyEncoder::G4(e),
+        }
+    }
+    pub(crate) fn encode_transitions(&mut self, transitions: &[u16], width: u16) {
+        match self {
+            AnyEncoder::G3(e) => e.encode_transitions(transitions, width),
+            AnyEncoder::G4(e) => e.encode_transitions(transitions, width),
+        }
+    }
+    pub(crate) fn encode_line(&mut self, pels: impl Iterator<Item=Color>, width: u16) {
+        match self {
+            AnyEncoder::G3(e) => e.encode_line(pels, width),
+            AnyEncoder::G4(e) => e.encode_line(pels, width),
+        }
+    }
+    pub(crate) fn finish(self) -> Vec<u8> {
+        match self {
+            AnyEncoder::G3(e) => e.finish().finish(),
+            AnyEncoder::G4(e) => e.finish().finish(),
+        }
+    }
+}
+
+/// Convert a raw coded stream from one coding to another, line by line,
+/// without expanding the lines into pels.
+///
+/// Group 3 input is decoded until the RTC, Group 4 input until the end-of-block marker.
+/// Damaged Group 3 lines are replaced as in `decoder::decode_g3_checked`.
+pub fn transcode(input: &[u8], width: u16, from: Coding, to: Coding) -> Option<Vec<u8>> {
+    let mut encoder = AnyEncoder::new(to);
+    decode_any(input, width, from, |transitions| encoder.encode_transitions(transitions, width))?;
+    Some(encoder.finish())
+}
+
+/// Decode a raw coded stream of any of the supported codings.
+pub(crate) fn decode_any(input: &[u8], width: u16, coding: Coding, line_cb: impl FnMut(&[u16])) -> Option<()> {
+    match coding {
+        Coding::Group3OneD => { decode_g3_checked(input.iter().cloned(), width, false, line_cb); }
+        Coding::Group3TwoD { .. } => { decode_g3_checked(input.iter().cloned(), width, true, line_cb); }
+        Coding::Group4 => decode_g4(input.iter().cloned(), width, None, line_cb)?,
+    }
+    Some(())
+}
+
+/// Convert one TIFF image to a different coding. The result has a single strip.
+pub fn transcode_image(image: &Image, to: Coding) -> Option<Image> {
+    let width = u16::try_from(image.width).ok()?;
+    let mut encoder = AnyEncoder::new(to);
+    image.decode(|transitions| encoder.encode_transitions(transitions, width))?;
+    Some(Image {
+        coding: to,
+        rows_per_strip: image.height,
+        strips: vec![encoder.finish()],
+        ..image.clone()
+    })
+}
+
+/// Convert all pages of a TIFF file to a different coding.
+pub fn transcode_tiff(input: &[u8], to: Coding, options: Options) -> Option<Vec<u8>> {
+    let images = tiff::read(input)?;
+    let images = images.iter().map(|image| transcode_image(image, to)).collect::<Option<Vec<_>>>()?;
+    Some(tiff::write(&images, options))
+}
+
+#[test]
+fn test_transcode() {
+    use crate::{decoder::decode_g4, Resolution};
+    use crate::tiff::Photometric;
+
+    let width = 100;
+    let rows: Vec<Vec<u16>> = (0 .. 20u16).map(|y| match y % 4 {
+        0 => vec![],
+        1 => vec![y, y + 30],
+        2 => vec![0, 10, 50, 99],
+        _ => vec![y, y + 1, 60],
+    }).collect();
+    let mut encoder = AnyEncoder::new(Coding::Group4);
+    for row in &rows {
+        encoder.encode_transitions(row, width);
+    }
+    let g4 = encoder.finish();
+
+    let g3 = transcode(&g4, width, Coding::Group4, Coding::Group3OneD).unwrap();
+    let mr = transcode(&g3, width, Coding::Group3OneD, Coding::Group3TwoD { k: 4 }).unwrap();
+    assert_eq!(transcode(&mr, width, Coding::Group3TwoD { k: 4 }, Coding::Group4).unwrap(), g4);
+
+    let image = Image {
+        width: width as u32,
+        height: rows.len() as u32,
+        coding: Coding::Group3TwoD { k: 4 },
+        photometric: Photometric::WhiteIsZero,
+        resolution: Some(Resolution::FINE),
+        rows_per_strip: rows.len() as u32,
+        strips: vec![mr],
+    };
+    let options = Options { byte_order: tiff::ByteOrder::BigEndian, big_tiff: false };
+    let file = tiff::write(&[image.clone(), image], options);
+    let file = transcode_tiff(&file, Coding::Group4, Options::default()).unwrap();
+    let pages = tiff::read(&file).unwrap();
+    assert_eq!(pages.len(), 2);
+    assert_eq!(pages[1].coding, Coding::Group4);
+    assert_eq!(pages[1].resolution, Some(Resolution::FINE));
+
+    let mut decoded = vec![];
+    decode_g4(pages[1].strips[0].iter().cloned(), width, None, |line| decoded.push(line.to_vec())).unwrap();
+    assert_eq!(decoded, rows);
+}
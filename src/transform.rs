@@ -0,0 +1,155 @@
+use crate::bitmap::Bitmap;
+use crate::runs;
+
+/// Rotations (clockwise) and flips of a page.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Transform {
+    Rotate90,
+    Rotate180,
+    Rotate270,
+    /// Mirror left and right.
+    FlipHorizontal,
+    /// Mirror top and bottom.
+    FlipVertical,
+}
+impl Transform {
+    /// Whether width and height are swapped.
+    pub fn swaps_axes(self) -> bool {
+        matches!(self, Transform::Rotate90 | Transform::Rotate270)
+    }
+}
+
+/// Rotate a page given as rows of color changes by 180 degrees.
+///
+/// This works on the runs directly and never expands the rows into pels.
+pub fn rotate_180_lines<L: AsRef<[u16]>>(lines: &[L], width: u16) -> Vec<Vec<u16>> {
+    lines.iter().rev().map(|line| runs::mirror(line.as_ref(), width)).collect()
+}
+
+/// Transform a page given as rows of color changes.
+///
+/// Returns the new rows and the new width. Rotations by 90 and 270 degrees go through
+/// a packed `Bitmap`, all other transforms work on the runs directly.
+pub fn transform_lines<L: AsRef<[u16]>>(lines: &[L], width: u16, transform: Transform) -> (Vec<Vec<u16>>, u16) {
+    match transform {
+        Transform::Rotate180 => (rotate_180_lines(lines, width), width),
+        Transform::FlipHorizontal => (lines.iter().map(|line| runs::mirror(line.as_ref(), width)).collect(), width),
+        Transform::FlipVertical => (lines.iter().rev().map(|line| runs::normalize(line.as_ref(), width)).collect(), width),
+        Transform::Rotate90 | Transform::Rotate270 => {
+            let bitmap = transform_bitmap(&Bitmap::from_lines(lines, width), transform);
+            (bitmap.lines().collect(), bitmap.width)
+        }
+    }
+}
+
+/// Transform a packed bitmap.
+pub fn transform_bitmap(bitmap: &Bitmap, transform: Transform) -> Bitmap {
+    match transform {
+        Transform::Rotate90 => flip_horizontal(&transpose(bitmap)),
+        Transform::Rotate180 => flip_horizontal(&flip_vertical(bitmap)),
+        Transform::Rotate270 => flip_vertical(&transpose(bitmap)),
+        Transform::FlipHorizontal => flip_horizontal(bitmap),
+        Transform::FlipVertical => flip_vertical(bitmap),
+    }
+}
+
+/// Mirror the bitmap along its main diagonal, so that column `x` becomes row `x`.
+///
+/// The bitmap is processed in blocks of 8x8 pels.
+pub fn transpose(bitmap: &Bitmap) -> Bitmap {
+    let mut out = Bitmap::new(bitmap.height, bitmap.width);
+    let (stride, out_stride) = (bitmap.stride(), out.stride());
+    let (height, width) = (bitmap.height as usize, bitmap.width as usize);
+    for by in (0 .. height).step_by(8) {
+        for bx in 0 .. stride {
+            // gather the block, first row in the most significant byte
+            let mut block = 0u64;
+            for y in by .. by + 8 {
+                let byte = if y < height { bitmap.data[y * stride + bx] } else { 0 };
+                block = block << 8 | byte as u64;
+            }
+            if block == 0 {
+                continue;
+            }
+            let block = transpose_8x8(block);
+            for (i, &byte) in block.to_be_bytes().iter().enumerate() {
+                let y = bx * 8 + i;
+                if y >= width {
+                    break;
+                }
+                out.data[y * out_stride + by / 8] = byte;
+            }
+        }
+    }
+    out
+}
+
+/// Transpose an 8x8 bit matrix (Hacker's Delight, 7-3).
+fn transpose_8x8(mut x: u64) -> u64 {
+    x = (x & 0xAA55_AA55_AA55_AA55) | ((x & 0x00AA_00AA_00AA_00AA) << 7) | ((x >> 7) & 0x00AA_00AA_00AA_00AA);
+    x = (x & 0xCCCC_3333_CCCC_3333) | ((x & 0x0000_CCCC_0000_CCCC) << 14) | ((x >> 14) & 0x0000_CCCC_0000_CCCC);
+    x = (x & 0xF0F0_F0F0_0F0F_0F0F) | ((x & 0x0000_0000_F0F0_F0F0) << 28) | ((x >> 28) & 0x0000_0000_F0F0_F0F0);
+    x
+}
+
+/// Reverse the order of the rows.
+pub fn flip_vertical(bitmap: &Bitmap) -> Bitmap {
+    let stride = bitmap.stride().max(1);
+    let mut out = Bitmap::new(bitmap.width, bitmap.height);
+    for (dst, src) in out.data.chunks_mut(stride).zip(bitmap.data.chunks(stride).rev()) {
+        dst.copy_from_slice(src);
+    }
+    out
+}
+
+/// Reverse the order of the pels in every row.
+pub fn flip_horizontal(bitmap: &Bitmap) -> Bitmap {
+    let stride = bitmap.stride().max(1);
+    // after reversing the bytes the padding bits are at the start of the row
+    let pad = (8 - bitmap.width % 8) % 8;
+    let mut out = Bitmap::new(bitmap.width, bitmap.height);
+    for (dst, src) in out.data.chunks_mut(stride).zip(bitmap.data.chunks(stride)) {
+        for (d, &s) in dst.iter_mut().zip(src.iter().rev()) {
+            *d = s.reverse_bits();
+        }
+        if pad > 0 {
+            for i in 0 .. dst.len() {
+                let next = dst.get(i + 1).cloned().unwrap_or(0);
+                dst[i] = dst[i] << pad | next >> (8 - pad);
+            }
+        }
+    }
+    out
+}
+
+#[test]
+fn test_transform() {
+    let (width, height) = (21, 13);
+    let lines: Vec<Vec<u16>> = (0 .. height).map(|y| match y % 3 {
+        0 => vec![y, y + 5],
+        1 => vec![0, 2, 20 - y % 7],
+        _ => vec![],
+    }).collect();
+    let bitmap = Bitmap::from_lines(&lines, width);
+    let pel = |x: u16, y: u16| bitmap.get(x, y);
+
+    let check = |t: Transform, expected: &dyn Fn(u16, u16) -> bool| {
+        let out = transform_bitmap(&bitmap, t);
+        if t.swaps_axes() {
+            assert_eq!((out.width, out.height), (height, width));
+        }
+        for y in 0 .. out.height {
+            for x in 0 .. out.width {
+                assert_eq!(out.get(x, y), expected(x, y), "{:?} at {}, {}", t, x, y);
+            }
+        }
+        let (out_lines, out_width) = transform_lines(&lines, width, t);
+        assert_eq!(out_width, out.width);
+        assert_eq!(out_lines, out.lines().collect::<Vec<_>>());
+    };
+    check(Transform::Rotate90, &|x, y| pel(y, height - 1 - x));
+    check(Transform::Rotate180, &|x, y| pel(width - 1 - x, height - 1 - y));
+    check(Transform::Rotate270, &|x, y| pel(width - 1 - y, x));
+    check(Transform::FlipHorizontal, &|x, y| pel(width - 1 - x, y));
+    check(Transform::FlipVertical, &|x, y| pel(x, height - 1 - y));
+}
@@ -1,8 +1,5 @@
-#![feature(slice_split_once)]
-
-use fax::{VecWriter, decoder, decoder::pels, BitWriter, Bits, Color};
-use std::io::Write;
-use std::fs::{self, File};
+use fax::{decoder, bitmap::Bitmap, netpbm};
+use std::fs;
 use std::path::Path;
 
 #[test]
@@ -31,39 +28,26 @@ fn main() {
 }
 
 fn test_file(fax_path: &Path, pbm_path: &Path) -> bool {
-    let ref_data = std::fs::read(pbm_path).unwrap();
-    let (header1, data) = ref_data.split_once(|&b| b == b'\n').unwrap();
-    assert_eq!(header1, b"P4");
-    let (header2, ref_image) = data.split_once(|&b| b == b'\n').unwrap();
-    let header2 = std::str::from_utf8(header2).unwrap();
-    let (w, h) = header2.split_once(" ").unwrap();
-    let width: u16 = w.parse().unwrap();
-    let h: u16 = h.parse().unwrap();
-
-    let mut ref_lines = ref_image.chunks_exact((width as usize + 7) / 8);
+    let ref_data = fs::read(pbm_path).unwrap();
+    let images = netpbm::read(&ref_data).unwrap();
+    let reference = images[0].to_bitmap().unwrap();
+    let width = reference.width;
 
     let data = fs::read(fax_path).unwrap();
-    let mut height = 0;
+    let mut bitmap = Bitmap::empty(width);
+    // the streams leave out white lines at the bottom, only the height tells about them
+    decoder::decode_g4(data.iter().cloned(), width, Some(reference.height), |transitions| {
+        bitmap.push_line(transitions);
+    }).unwrap();
+
     let mut errors = 0;
-    decoder::decode_g4(data.iter().cloned(), width, None,  |transitions| {
-        let mut writer = VecWriter::new();
-        for c in pels(transitions, width) {
-            let bit = match c {
-                Color::Black => Bits { data: 1, len: 1 },
-                Color::White => Bits { data: 0, len: 1 }
-            };
-            writer.write(bit);
-        }
-        writer.pad();
-        let data = writer.finish();
-        let ref_line = ref_lines.next().unwrap();
-        if ref_line != data {
-            println!("line {height} mismatch");
+    for (y, (line, ref_line)) in bitmap.lines().zip(reference.lines()).enumerate() {
+        if line != ref_line {
+            println!("line {y} mismatch");
             errors += 1;
         }
-        height += 1;
-    });
+    }
 
-    dbg!(height, h, errors);
-    height == h && errors == 0
+    dbg!(bitmap.height, reference.height, errors);
+    bitmap.height == reference.height && errors == 0
 }